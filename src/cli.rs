@@ -0,0 +1,625 @@
+//! The command-line tool.
+//!
+//! `check <files/globs>` parses and validates each file, following its
+//! `@include`s, and prints the diagnostics rustc-style or, with
+//! `--format json`, as a JSON array. The exit code is 1 if any file has an
+//! error or cannot be read.
+//!
+//! `fmt <paths>` formats files in place, recursing into directories for
+//! `.q` files and skipping those matched by the ignore file (gitignore
+//! syntax, `.qfmtignore` by default). With `--check` it changes nothing,
+//! printing a unified diff for each unformatted file and exiting with 1.
+//! With no paths, or `-`, it formats standard input to standard output.
+//!
+//! `highlight <files>` prints files with terminal colors or, with
+//! `--format html`, as HTML (see [`crate::highlight`]).
+//!
+//! `lsp` runs the language server (see [`crate::lsp`]) and `repl` an
+//! interactive session (see [`crate::repl`]).
+
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use ignore::gitignore::Gitignore;
+use serde_json::{Value as Json, json};
+use similar::TextDiff;
+use walkdir::WalkDir;
+
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::format::format;
+use crate::highlight;
+use crate::include::{FsProvider, SourceFile, SourceMap, parse_with_includes};
+use crate::line_index::Encoding;
+use crate::lsp;
+use crate::repl;
+use crate::schema::{DuplicateKeys, ValidateOptions};
+
+#[derive(Debug, Parser)]
+#[command(name = "web-assembly", about = "Tools for the question DSL")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Parse and validate files, printing their diagnostics.
+    Check(CheckArgs),
+    /// Format files in place, or check that they are formatted.
+    Fmt(FmtArgs),
+    /// Print files with syntax highlighting.
+    Highlight(HighlightArgs),
+    /// Run the language server on standard input and output.
+    Lsp(LspArgs),
+    /// Try directives interactively.
+    Repl,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct CheckArgs {
+    /// Files or glob patterns to check.
+    #[arg(required = true)]
+    pub files: Vec<String>,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    pub format: OutputFormat,
+    /// How a directive that repeats a key is read.
+    #[arg(long, value_enum, default_value_t = DuplicateKeyPolicy::Error)]
+    pub duplicate_keys: DuplicateKeyPolicy,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct LspArgs {
+    /// How a directive that repeats a key is read.
+    #[arg(long, value_enum, default_value_t = DuplicateKeyPolicy::Error)]
+    pub duplicate_keys: DuplicateKeyPolicy,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct FmtArgs {
+    /// Files or directories to format; `-` or none reads standard input.
+    pub paths: Vec<String>,
+    /// Print a diff for each unformatted file instead of rewriting it.
+    #[arg(long)]
+    pub check: bool,
+    /// File of gitignore-style patterns for paths to skip.
+    #[arg(long, default_value = IGNORE_FILE)]
+    pub ignore_path: PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct HighlightArgs {
+    /// Files to highlight; `-` or none reads standard input.
+    pub files: Vec<String>,
+    #[arg(long, value_enum, default_value_t = HighlightFormat::Ansi)]
+    pub format: HighlightFormat,
+    /// Leave the `<style>` element out of HTML output.
+    #[arg(long)]
+    pub no_style: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HighlightFormat {
+    /// Terminal colors.
+    Ansi,
+    /// A `<pre>` fragment with a CSS class per token kind.
+    Html,
+}
+
+/// Extension of the files `fmt` picks up when walking a directory.
+pub const EXTENSION: &str = "q";
+
+/// Ignore file `fmt` reads unless given another.
+pub const IGNORE_FILE: &str = ".qfmtignore";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Human,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DuplicateKeyPolicy {
+    Error,
+    LastWins,
+    Collect,
+}
+
+impl From<DuplicateKeyPolicy> for DuplicateKeys {
+    fn from(policy: DuplicateKeyPolicy) -> Self {
+        match policy {
+            DuplicateKeyPolicy::Error => DuplicateKeys::Error,
+            DuplicateKeyPolicy::LastWins => DuplicateKeys::LastWins,
+            DuplicateKeyPolicy::Collect => DuplicateKeys::Collect,
+        }
+    }
+}
+
+/// Run `cli`, reading standard input from `input` and writing results to
+/// `out` and failures to run (unreadable files, patterns matching nothing)
+/// to `err`. Returns the exit code.
+pub fn run(cli: Cli, input: &mut dyn Read, out: &mut dyn Write, err: &mut dyn Write) -> i32 {
+    let result = match cli.command {
+        Command::Check(args) => check(&args, out, err),
+        Command::Fmt(args) => fmt(&args, input, out, err),
+        Command::Highlight(args) => highlight(&args, input, out, err),
+        Command::Lsp(args) => {
+            let options = ValidateOptions {
+                duplicate_keys: args.duplicate_keys.into(),
+            };
+            lsp::serve_stdio(options)
+                .map(|()| 0)
+                .map_err(io::Error::other)
+        }
+        Command::Repl => repl::run().map(|()| 0).map_err(io::Error::other),
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            let _ = writeln!(err, "error: {e}");
+            2
+        }
+    }
+}
+
+fn check(args: &CheckArgs, out: &mut dyn Write, err: &mut dyn Write) -> io::Result<i32> {
+    let options = ValidateOptions {
+        duplicate_keys: args.duplicate_keys.into(),
+    };
+    let mut failed = false;
+    let mut files = 0;
+    let (mut errors, mut warnings) = (0, 0);
+    let mut json = Vec::new();
+    for path in expand(&args.files, err)? {
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                writeln!(err, "error: cannot read `{path}`: {e}")?;
+                failed = true;
+                continue;
+            }
+        };
+        files += 1;
+        let bundle = parse_with_includes(&path, &text, &FsProvider);
+        let mut diagnostics = bundle.diagnostics.clone();
+        diagnostics.extend(bundle.validate_with(&options));
+        for diagnostic in &diagnostics {
+            match diagnostic.severity {
+                Severity::Error => errors += 1,
+                Severity::Warning => warnings += 1,
+                _ => {}
+            }
+            match args.format {
+                OutputFormat::Human => writeln!(out, "{}", bundle.sources.render(diagnostic))?,
+                OutputFormat::Json => json.push(to_json(&bundle.sources, diagnostic)),
+            }
+        }
+    }
+    match args.format {
+        OutputFormat::Human => writeln!(
+            out,
+            "checked {files} {}: {errors} {}, {warnings} {}",
+            plural(files, "file"),
+            plural(errors, "error"),
+            plural(warnings, "warning"),
+        )?,
+        OutputFormat::Json => writeln!(out, "{}", Json::Array(json))?,
+    }
+    Ok(i32::from(failed || errors > 0))
+}
+
+/// The paths named by `patterns`: each one with glob metacharacters is
+/// expanded, the rest are taken as written. Repeats are dropped; a pattern
+/// matching nothing is reported to `err`.
+fn expand(patterns: &[String], err: &mut dyn Write) -> io::Result<Vec<String>> {
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        if !pattern.contains(['*', '?', '[']) {
+            paths.push(pattern.clone());
+            continue;
+        }
+        let matches = glob::glob(pattern)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+            .filter_map(Result::ok)
+            .filter(|p| p.is_file())
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        if matches.is_empty() {
+            writeln!(err, "warning: `{pattern}` matches no files")?;
+        }
+        paths.extend(matches);
+    }
+    let mut seen = std::collections::HashSet::new();
+    paths.retain(|p| seen.insert(p.clone()));
+    Ok(paths)
+}
+
+fn fmt(
+    args: &FmtArgs,
+    input: &mut dyn Read,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> io::Result<i32> {
+    if args.paths.is_empty() || args.paths == ["-"] {
+        let mut src = String::new();
+        input.read_to_string(&mut src)?;
+        let formatted = format(&src);
+        if !args.check {
+            out.write_all(formatted.as_bytes())?;
+            return Ok(0);
+        }
+        write!(out, "{}", diff("<stdin>", &src, &formatted))?;
+        return Ok(i32::from(formatted != src));
+    }
+
+    let ignore = load_ignore(&args.ignore_path)?;
+    let mut failed = false;
+    let mut unformatted = 0;
+    for path in walk(&args.paths, &ignore, err)? {
+        let shown = path.display().to_string();
+        let src = match std::fs::read_to_string(&path) {
+            Ok(src) => src,
+            Err(e) => {
+                writeln!(err, "error: cannot read `{shown}`: {e}")?;
+                failed = true;
+                continue;
+            }
+        };
+        let formatted = format(&src);
+        if formatted == src {
+            continue;
+        }
+        unformatted += 1;
+        if args.check {
+            write!(out, "{}", diff(&shown, &src, &formatted))?;
+        } else if let Err(e) = std::fs::write(&path, formatted) {
+            writeln!(err, "error: cannot write `{shown}`: {e}")?;
+            failed = true;
+        }
+    }
+    if args.check && unformatted > 0 {
+        writeln!(
+            err,
+            "{unformatted} {} not formatted",
+            if unformatted == 1 {
+                "file is"
+            } else {
+                "files are"
+            }
+        )?;
+    }
+    Ok(i32::from(failed || (args.check && unformatted > 0)))
+}
+
+fn highlight(
+    args: &HighlightArgs,
+    input: &mut dyn Read,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> io::Result<i32> {
+    let mut sources = Vec::new();
+    if args.files.is_empty() || args.files == ["-"] {
+        let mut src = String::new();
+        input.read_to_string(&mut src)?;
+        sources.push(src);
+    }
+    let mut failed = false;
+    for path in args.files.iter().filter(|p| *p != "-") {
+        match std::fs::read_to_string(path) {
+            Ok(src) => sources.push(src),
+            Err(e) => {
+                writeln!(err, "error: cannot read `{path}`: {e}")?;
+                failed = true;
+            }
+        }
+    }
+    if args.format == HighlightFormat::Html && !args.no_style {
+        writeln!(out, "<style>\n{}</style>", highlight::STYLESHEET)?;
+    }
+    for src in &sources {
+        match args.format {
+            HighlightFormat::Ansi => write!(out, "{}", highlight::to_ansi(src))?,
+            HighlightFormat::Html => write!(out, "{}", highlight::to_html(src))?,
+        }
+    }
+    Ok(i32::from(failed))
+}
+
+/// The ignore rules in `path`; none if the file does not exist.
+fn load_ignore(path: &Path) -> io::Result<Gitignore> {
+    if !path.exists() {
+        return Ok(Gitignore::empty());
+    }
+    // Patterns are relative to the file's directory, which `walk` compares
+    // against absolute paths.
+    let (ignore, error) = Gitignore::new(absolute(path));
+    match error {
+        Some(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        None => Ok(ignore),
+    }
+}
+
+/// The files `fmt` formats: files named in `paths` and the `.q` files under
+/// directories named there, in order, leaving out what `ignore` matches.
+fn walk(paths: &[String], ignore: &Gitignore, err: &mut dyn Write) -> io::Result<Vec<PathBuf>> {
+    let ignored = |path: &Path, is_dir: bool| {
+        // Paths outside the ignore file's directory are never ignored.
+        let path = absolute(path);
+        path.starts_with(ignore.path())
+            && ignore
+                .matched_path_or_any_parents(&path, is_dir)
+                .is_ignore()
+    };
+    let mut files = Vec::new();
+    for path in paths.iter().map(PathBuf::from) {
+        if !path.is_dir() {
+            if !ignored(&path, false) {
+                files.push(path);
+            }
+            continue;
+        }
+        let entries = WalkDir::new(&path)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|e| e.depth() == 0 || !ignored(e.path(), e.file_type().is_dir()));
+        for entry in entries {
+            match entry {
+                Ok(e)
+                    if e.file_type().is_file()
+                        && e.path().extension() == Some(EXTENSION.as_ref()) =>
+                {
+                    files.push(e.into_path());
+                }
+                Ok(_) => {}
+                Err(e) => writeln!(err, "warning: {e}")?,
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// `path` from the file system root, with symlinks resolved when it exists,
+/// so paths given relative and absolute compare equal.
+fn absolute(path: &Path) -> PathBuf {
+    path.canonicalize()
+        .or_else(|_| std::path::absolute(path))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// A unified diff from `old` to `new`, empty if they are equal.
+fn diff(path: &str, old: &str, new: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(path, path)
+        .to_string()
+}
+
+fn plural(n: usize, word: &str) -> String {
+    if n == 1 {
+        word.to_string()
+    } else {
+        format!("{word}s")
+    }
+}
+
+/// `diagnostic` as a JSON object, positions 1-based and columns in
+/// characters.
+fn to_json(sources: &SourceMap, diagnostic: &Diagnostic) -> Json {
+    let file = sources.file(diagnostic.file);
+    let end = position(file, diagnostic.span.end);
+    let related: Vec<Json> = diagnostic
+        .related
+        .iter()
+        .map(|r| {
+            json!({
+                "line": r.line,
+                "column": r.column,
+                "span": [r.span.start, r.span.end],
+                "message": r.message,
+            })
+        })
+        .collect();
+    json!({
+        "file": file.path,
+        "severity": diagnostic.severity.to_string(),
+        "code": diagnostic.code,
+        "message": diagnostic.message,
+        "line": diagnostic.line,
+        "column": diagnostic.column,
+        "end_line": end.0,
+        "end_column": end.1,
+        "span": [diagnostic.span.start, diagnostic.span.end],
+        "expected": diagnostic.expected,
+        "found": diagnostic.found,
+        "help": diagnostic.help,
+        "related": related,
+        "rendered": sources.render(diagnostic),
+    })
+}
+
+fn position(file: &SourceFile, offset: usize) -> (u32, u32) {
+    let at = file.index.line_col(offset, Encoding::Char);
+    (at.line + 1, at.col + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `args` (with `$DIR` replaced) in a fresh directory holding
+    /// `files`. Returns the exit code, output, errors and the files' text
+    /// afterwards.
+    fn run_cli(
+        dir: &str,
+        files: &[(&str, &str)],
+        args: &[&str],
+        stdin: &str,
+    ) -> (i32, String, String, Vec<String>) {
+        let dir = std::env::temp_dir().join(format!("cli-{dir}-{}", std::process::id()));
+        for (name, text) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        }
+        std::fs::create_dir_all(&dir).unwrap();
+        // `$REL` is the directory relative to the working directory.
+        let up = std::env::current_dir().unwrap().components().count() - 1;
+        let rel = "../".repeat(up) + dir.strip_prefix("/").unwrap().to_str().unwrap();
+        let argv = std::iter::once("web-assembly".to_string()).chain(args.iter().map(|a| {
+            a.replace("$DIR", dir.to_str().unwrap())
+                .replace("$REL", &rel)
+        }));
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let code = run(
+            Cli::parse_from(argv),
+            &mut stdin.as_bytes(),
+            &mut out,
+            &mut err,
+        );
+        let after = files
+            .iter()
+            .map(|(name, _)| std::fs::read_to_string(dir.join(name)).unwrap())
+            .collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let err = String::from_utf8(err).unwrap();
+        (code, out, err, after)
+    }
+
+    #[test]
+    fn check_reports_diagnostics() {
+        let files = [
+            ("good.q", "@option prompt=\"Pick one\"\n* yes\n- no\n"),
+            ("bad.q", "@option prompt=\"Pick\" points=x\n* a\n- b\n"),
+        ];
+        // No body text above the answers, so the header's prompt is used.
+        let question = crate::question::parse_question(files[0].1).unwrap();
+        assert_eq!(question.prompt, "Pick one");
+        let (code, out, err, _) =
+            run_cli("human", &files, &["check", "$DIR/good.q", "$DIR/*.q"], "");
+        assert_eq!(code, 1);
+        assert_eq!(err, "");
+        assert!(out.contains("bad.q:1:"), "{out}");
+        assert!(
+            out.contains("1 | @option prompt=\"Pick\" points=x\n"),
+            "{out}"
+        );
+        assert!(out.contains('^'), "{out}");
+        assert!(
+            out.ends_with("checked 2 files: 1 error, 0 warnings\n"),
+            "{out}"
+        );
+
+        let (code, out, err, _) = run_cli(
+            "clean",
+            &files[..1],
+            &["check", "$DIR/good.q", "$DIR/none*"],
+            "",
+        );
+        assert_eq!(code, 0);
+        assert!(err.contains("matches no files"), "{err}");
+        assert_eq!(out, "checked 1 file: 0 errors, 0 warnings\n");
+
+        let (code, _, err, _) = run_cli("missing", &[], &["check", "$DIR/missing.q"], "");
+        assert_eq!(code, 1);
+        assert!(err.starts_with("error: cannot read"), "{err}");
+    }
+
+    #[test]
+    fn check_json() {
+        let files = [("a.q", "@option prompt=\"Pick\" points=x\n")];
+        let (code, out, _, _) = run_cli(
+            "json",
+            &files,
+            &["check", "--format", "json", "$DIR/a.q"],
+            "",
+        );
+        assert_eq!(code, 1);
+        let json: Json = serde_json::from_str(&out).unwrap();
+        let first = &json[0];
+        assert!(first["file"].as_str().unwrap().ends_with("a.q"));
+        assert_eq!(first["severity"], "error");
+        assert_eq!(first["line"], 1);
+        assert_eq!(first["column"], 30);
+        assert_eq!(first["end_column"], 31);
+        assert!(first["rendered"].as_str().unwrap().contains('^'));
+    }
+
+    #[test]
+    fn fmt_in_place_and_check() {
+        let files = [
+            ("bank/a.q", "@option   prompt=\"A\"\n* x\n"),
+            ("bank/skip/b.q", "@option   prompt=\"B\"\n"),
+            ("bank/notes.txt", "@option   prompt=\"C\"\n"),
+            ("bank/done.q", "@option prompt=A\n"),
+            (".qfmtignore", "skip/\n"),
+        ];
+        let ignore = ["--ignore-path", "$DIR/.qfmtignore"];
+        let args = [&["fmt", "--check", "$DIR/bank"][..], &ignore].concat();
+        let (code, out, err, after) = run_cli("fmt-check", &files, &args, "");
+        assert_eq!(code, 1);
+        assert!(
+            out.contains("-@option   prompt=\"A\"\n+@option prompt=A\n"),
+            "{out}"
+        );
+        assert!(out.contains("bank/a.q"), "{out}");
+        assert_eq!(err, "1 file is not formatted\n");
+        assert_eq!(after[0], files[0].1);
+
+        let args = [&["fmt", "$DIR/bank", "$DIR/bank/skip/b.q"][..], &ignore].concat();
+        let (code, out, _, after) = run_cli("fmt-write", &files, &args, "");
+        assert_eq!((code, out.as_str()), (0, ""));
+        assert_eq!(after[0], "@option prompt=A\n* x\n");
+        assert_eq!(after[1..4], [files[1].1, files[2].1, files[3].1]);
+
+        // One side relative, the other absolute.
+        for args in [
+            [
+                "fmt",
+                "--check",
+                "--ignore-path",
+                "$DIR/.qfmtignore",
+                "$REL/bank",
+            ],
+            [
+                "fmt",
+                "--check",
+                "--ignore-path",
+                "$REL/.qfmtignore",
+                "$DIR/bank",
+            ],
+        ] {
+            let (code, out, err, _) = run_cli("fmt-mixed", &files, &args, "");
+            assert_eq!((code, err.as_str()), (1, "1 file is not formatted\n"));
+            assert!(!out.contains("skip/b.q"), "{out}");
+        }
+    }
+
+    #[test]
+    fn fmt_stdin() {
+        let (code, out, _, _) = run_cli("stdin", &[], &["fmt"], "@meta   a=1\n");
+        assert_eq!((code, out.as_str()), (0, "@meta a=1\n"));
+        let (code, out, _, _) =
+            run_cli("stdin-check", &[], &["fmt", "--check", "-"], "@meta a=1\n");
+        assert_eq!((code, out.as_str()), (0, ""));
+        let (code, out, _, _) = run_cli("stdin-diff", &[], &["fmt", "--check"], "@meta  a=1\n");
+        assert_eq!(code, 1);
+        assert!(out.starts_with("--- <stdin>\n+++ <stdin>\n"), "{out}");
+    }
+
+    #[test]
+    fn highlight_html() {
+        let files = [("a.q", "@meta tags=[x]\n")];
+        let args = ["highlight", "--format", "html", "$DIR/a.q", "$DIR/b.q"];
+        let (code, out, err, _) = run_cli("highlight", &files, &args, "");
+        assert_eq!(code, 1);
+        assert!(err.contains("cannot read"), "{err}");
+        assert!(out.starts_with("<style>\n.q-highlight"), "{out}");
+        assert!(
+            out.ends_with("<span class=\"q-punctuation\">]</span>\n</code></pre>\n"),
+            "{out}"
+        );
+        let (code, out, _, _) = run_cli("highlight-stdin", &[], &["highlight"], "Why?\n");
+        assert_eq!((code, out.as_str()), (0, "Why?\n"));
+    }
+}
@@ -0,0 +1,590 @@
+//! Deserialize directives into Rust types with serde.
+//!
+//! A directive deserializes like a map of its pairs, so
+//! `@option shuffle=true points=3` fills a `#[derive(Deserialize)]` struct
+//! with `shuffle` and `points` fields. Positional arguments count as the
+//! pairs their schema names them, so `@option "Why?"` fills `prompt`. As an
+//! enum, the directive name picks the variant. Errors are [`Diagnostic`]s
+//! located at the offending key or value, or at the whole directive for
+//! missing fields. A repeated key is a duplicate field unless
+//! [`from_str_with`] is given another [`DuplicateKeys`](crate::schema::DuplicateKeys)
+//! policy.
+
+use std::fmt;
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+
+use crate::diagnostic::{Diagnostic, codes};
+use crate::eval::evaluate;
+use crate::parser::{Directive, Pair, Span, parse_document};
+use crate::schema::{ValidateOptions, bind_args, is_preamble, merge_duplicates};
+use crate::value::Value;
+
+/// Deserialize the header block of `src`, with its variables resolved.
+///
+/// Sequences (`Vec<T>`, tuples) receive every header directive but `@define`
+/// and `@include`; any other type receives the first of those.
+pub fn from_str<T: DeserializeOwned>(src: &str) -> Result<T, Vec<Diagnostic>> {
+    from_str_with(src, &ValidateOptions::default())
+}
+
+/// [`from_str`], reading repeated keys as `options` says, as validation does.
+pub fn from_str_with<T: DeserializeOwned>(
+    src: &str,
+    options: &ValidateOptions,
+) -> Result<T, Vec<Diagnostic>> {
+    let (doc, errors) = evaluate(src, &parse_document(src)?);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let headers: Vec<Directive> = doc
+        .headers
+        .iter()
+        .filter(|h| !is_preamble(h))
+        .map(|h| merge_duplicates(&bind_args(h), options.duplicate_keys))
+        .collect();
+    let default = headers.first().map_or(0..0, |h| h.span.clone());
+    T::deserialize(Headers(&headers)).map_err(|e| vec![e.into_diagnostic(src, default)])
+}
+
+/// Deserialize a single parsed directive; `src` is the text its spans point into.
+pub fn from_directive<T: DeserializeOwned>(
+    src: &str,
+    directive: &Directive,
+) -> Result<T, Vec<Diagnostic>> {
+    T::deserialize(DirectiveDeserializer(&bind_args(directive)))
+        .map_err(|e| vec![e.into_diagnostic(src, directive.span.clone())])
+}
+
+#[derive(Debug)]
+struct Error {
+    message: String,
+    span: Option<Span>,
+}
+
+impl Error {
+    /// Locate the error at `span` unless a more precise span is already set.
+    fn at(mut self, span: &Span) -> Self {
+        self.span.get_or_insert_with(|| span.clone());
+        self
+    }
+
+    fn into_diagnostic(self, src: &str, default: Span) -> Diagnostic {
+        Diagnostic::error(
+            src,
+            codes::DESERIALIZE,
+            self.span.unwrap_or(default),
+            self.message,
+        )
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error {
+            message: msg.to_string(),
+            span: None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The header block of a document.
+struct Headers<'a>(&'a [Directive]);
+
+impl Headers<'_> {
+    fn first(&self) -> Result<DirectiveDeserializer<'_>, Error> {
+        self.0
+            .first()
+            .map(DirectiveDeserializer)
+            .ok_or_else(|| de::Error::custom("expected a header directive"))
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Headers<'_> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.first()?.deserialize_any(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0.first() {
+            Some(directive) => visitor.visit_some(DirectiveDeserializer(directive)),
+            None => visitor.visit_none(),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.first()?.deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(Seq(self.0.iter().map(DirectiveDeserializer)))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// One directive: a map of its pairs, or an enum variant named after it.
+#[derive(Clone, Copy)]
+struct DirectiveDeserializer<'a>(&'a Directive);
+
+impl DirectiveDeserializer<'_> {
+    /// The only pair of a directive, whose value scalar targets read.
+    fn single(&self) -> Result<&Pair, Error> {
+        match &self.0.pairs[..] {
+            [pair] => Ok(pair),
+            _ => Err(de::Error::custom(format!(
+                "expected exactly one `key=value` pair on `@{}`",
+                self.0.name
+            ))),
+        }
+    }
+}
+
+macro_rules! forward_to_single {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                let pair = self.single()?;
+                ValueDeserializer(&pair.value)
+                    .$method(visitor)
+                    .map_err(|e| e.at(&pair.value_span))
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for DirectiveDeserializer<'_> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_map(Pairs {
+            pairs: self.0.pairs.iter(),
+            current: None,
+        })
+    }
+
+    forward_to_single! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+        deserialize_f64 deserialize_char deserialize_str deserialize_string deserialize_seq
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_enum(self)
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 bytes byte_buf tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl<'de> de::EnumAccess<'de> for DirectiveDeserializer<'_> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<(S::Value, Self), Error> {
+        let name: de::value::StrDeserializer<'_, Error> = self.0.name.as_str().into_deserializer();
+        let variant = seed
+            .deserialize(name)
+            .map_err(|e| e.at(&self.0.name_span))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for DirectiveDeserializer<'_> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<S::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_any(self, visitor)
+    }
+}
+
+/// The pairs of a directive as a map.
+struct Pairs<'a> {
+    pairs: std::slice::Iter<'a, Pair>,
+    current: Option<&'a Pair>,
+}
+
+impl<'de> de::MapAccess<'de> for Pairs<'_> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        let Some(pair) = self.pairs.next() else {
+            return Ok(None);
+        };
+        self.current = Some(pair);
+        let key: de::value::StrDeserializer<'_, Error> = pair.key.as_str().into_deserializer();
+        seed.deserialize(key)
+            .map(Some)
+            .map_err(|e| e.at(&pair.key_span))
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<S::Value, Error> {
+        let pair = self.current.take().expect("value requested before its key");
+        seed.deserialize(ValueDeserializer(&pair.value))
+            .map_err(|e| e.at(&pair.value_span))
+    }
+}
+
+/// A typed [`Value`].
+struct ValueDeserializer<'a>(&'a Value);
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'_> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::Int(i) => visitor.visit_i64(*i),
+            Value::Float(x) => visitor.visit_f64(*x),
+            Value::Bool(b) => visitor.visit_bool(*b),
+            Value::String(s) => visitor.visit_str(s),
+            Value::List(items) => visitor.visit_seq(Seq(items.iter().map(ValueDeserializer))),
+            Value::Map(entries) => visitor.visit_map(Entries {
+                entries: entries.iter(),
+                current: None,
+            }),
+        }
+    }
+
+    /// Bare words are typed, so `id=7` is an integer; string targets take the
+    /// scalar's DSL spelling instead of rejecting it.
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::Int(_) | Value::Float(_) | Value::Bool(_) => {
+                visitor.visit_string(self.0.to_string())
+            }
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Unit variants are written as their name, e.g. `scoring=partial`.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::String(s) => {
+                let variant: de::value::StrDeserializer<'_, Error> = s.as_str().into_deserializer();
+                visitor.visit_enum(variant)
+            }
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char bytes byte_buf
+        unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct Seq<I>(I);
+
+impl<'de, I, D> de::SeqAccess<'de> for Seq<I>
+where
+    I: Iterator<Item = D>,
+    D: de::Deserializer<'de, Error = Error>,
+{
+    type Error = Error;
+
+    fn next_element_seed<S: DeserializeSeed<'de>>(
+        &mut self,
+        seed: S,
+    ) -> Result<Option<S::Value>, Error> {
+        self.0.next().map(|d| seed.deserialize(d)).transpose()
+    }
+}
+
+/// The entries of a `{k=v}` map value.
+struct Entries<'a> {
+    entries: std::slice::Iter<'a, (String, Value)>,
+    current: Option<&'a Value>,
+}
+
+impl<'de> de::MapAccess<'de> for Entries<'_> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        let Some((key, value)) = self.entries.next() else {
+            return Ok(None);
+        };
+        self.current = Some(value);
+        let key: de::value::StrDeserializer<'_, Error> = key.as_str().into_deserializer();
+        seed.deserialize(key).map(Some)
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<S::Value, Error> {
+        let value = self.current.take().expect("value requested before its key");
+        seed.deserialize(ValueDeserializer(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::Import;
+    use crate::schema::DuplicateKeys;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    enum Scoring {
+        AllOrNothing,
+        Partial,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct OptionCfg {
+        shuffle: bool,
+        points: u32,
+        #[serde(default)]
+        prompt: Option<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    enum Header {
+        Option(OptionCfg),
+        MultiOption { scoring: Scoring },
+        Meta { author: String },
+    }
+
+    #[test]
+    fn structs_and_enums() {
+        let cfg: OptionCfg =
+            from_str("# draft\n@option shuffle=true points=3 tags=[a, 7]\nQ?").unwrap();
+        assert_eq!(
+            cfg,
+            OptionCfg {
+                shuffle: true,
+                points: 3,
+                prompt: None,
+                tags: vec!["a".into(), "7".into()],
+            }
+        );
+        let headers: Vec<Header> =
+            from_str("@multi_option scoring=partial\n@meta author='Siti Nur'\n").unwrap();
+        assert_eq!(
+            headers,
+            [
+                Header::MultiOption {
+                    scoring: Scoring::Partial
+                },
+                Header::Meta {
+                    author: "Siti Nur".into()
+                },
+            ]
+        );
+        let import: Import = from_str("@matching_pair prompt=\"Match the capitals\"").unwrap();
+        assert!(matches!(import, Import::MatchingPair(p) if p == "Match the capitals"));
+        let map: BTreeMap<String, BTreeMap<String, f64>> = from_str("@x a={k=1, j=0.5}").unwrap();
+        assert_eq!(map["a"]["k"], 1.0);
+    }
+
+    #[test]
+    fn located_errors() {
+        let errors = |src: &'static str| {
+            let diags = from_str::<Header>(src).unwrap_err();
+            assert_eq!(diags.len(), 1);
+            let d = &diags[0];
+            assert_eq!(d.code, codes::DESERIALIZE);
+            (&src[d.span.clone()], d.message.clone())
+        };
+        assert_eq!(
+            errors("@option shuffle=true points=-1"),
+            ("-1", "invalid value: integer `-1`, expected u32".into())
+        );
+        assert_eq!(errors("@option shufle=true points=1").0, "shufle");
+        assert_eq!(
+            errors("@option points=1"),
+            ("@option points=1", "missing field `shuffle`".into())
+        );
+        assert_eq!(errors("@multi_option scoring=most").0, "most");
+        assert_eq!(errors("\n@nope x=1").0, "nope");
+        assert_eq!(
+            from_str::<Header>("@option x=").unwrap_err()[0].code,
+            codes::MISSING_VALUE
+        );
+        assert_eq!(
+            from_str::<OptionCfg>("@option shuffle=true points=${pts}").unwrap_err()[0].code,
+            codes::UNDEFINED_VARIABLE
+        );
+        let cfg: OptionCfg =
+            from_str("@option shuffle=true points=${pts}\n@define pts=4\n").unwrap();
+        assert_eq!(cfg.points, 4);
+        // `@define` and `@include` lines are not the directive read.
+        let src = "@define p=3\n@include path=x.q\n@option shuffle=true points=${p}\n";
+        assert_eq!(from_str::<OptionCfg>(src).unwrap().points, 3);
+        let headers: Vec<Header> = from_str(src).unwrap();
+        assert_eq!(headers.len(), 1);
+        assert_eq!(
+            errors("@define p=3\n@option points=${p}"),
+            ("@option points=${p}", "missing field `shuffle`".into())
+        );
+
+        let src = "@option shuffle=true points=1 points=2";
+        assert_eq!(
+            errors(src),
+            (
+                "@option shuffle=true points=1 points=2",
+                "duplicate field `points`".into()
+            )
+        );
+        let with = |duplicate_keys| ValidateOptions { duplicate_keys };
+        let cfg: OptionCfg = from_str_with(src, &with(DuplicateKeys::LastWins)).unwrap();
+        assert_eq!(cfg.points, 2);
+        #[derive(Debug, Deserialize)]
+        struct Collected {
+            points: Vec<u32>,
+        }
+        let cfg: Collected = from_str_with(src, &with(DuplicateKeys::Collect)).unwrap();
+        assert_eq!(cfg.points, [1, 2]);
+    }
+
+    #[test]
+    fn edge_cases() {
+        // No header, or only a preamble: nothing to read.
+        assert_eq!(from_str::<Option<OptionCfg>>("\n").unwrap(), None);
+        assert_eq!(
+            from_str::<Option<OptionCfg>>("@define x=1\n").unwrap(),
+            None
+        );
+        let errors = from_str::<OptionCfg>("").unwrap_err();
+        assert_eq!(
+            (errors[0].span.clone(), errors[0].message.as_str()),
+            (0..0, "expected a header directive")
+        );
+
+        let pair: (Header, Header) =
+            from_str("@meta author=ana\n@multi_option scoring=all_or_nothing\n").unwrap();
+        assert_eq!(
+            pair.1,
+            Header::MultiOption {
+                scoring: Scoring::AllOrNothing
+            }
+        );
+
+        // Values carry no spans of their own, so an enum nested in a list is
+        // reported at the list, naming the element.
+        #[derive(Debug, Deserialize)]
+        struct Rules {
+            #[allow(dead_code)]
+            scorings: Vec<Scoring>,
+        }
+        let src = "@rules scorings=[partial, most]";
+        let errors = from_str::<Rules>(src).unwrap_err();
+        assert_eq!(&src[errors[0].span.clone()], "[partial, most]");
+        assert!(
+            errors[0].message.contains("`most`"),
+            "{}",
+            errors[0].message
+        );
+    }
+}
@@ -0,0 +1,345 @@
+use std::fmt;
+
+use crate::include::FileId;
+use crate::line_index::{Encoding, LineIndex};
+use crate::parser::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+    Hint,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+            Severity::Hint => "hint",
+        })
+    }
+}
+
+/// Stable diagnostic codes, shown as `error[E0001]`.
+pub mod codes {
+    /// Input the grammar does not accept.
+    pub const SYNTAX: &str = "E0001";
+    /// An opened delimiter that is never closed.
+    pub const UNCLOSED: &str = "E0002";
+    /// `key=` without a value.
+    pub const MISSING_VALUE: &str = "E0003";
+    /// The first non-empty line is not a directive.
+    pub const MISSING_HEADER: &str = "E0004";
+    /// A directive name with no schema.
+    pub const UNKNOWN_DIRECTIVE: &str = "E0101";
+    /// A key the directive's schema does not declare.
+    pub const UNKNOWN_KEY: &str = "E0102";
+    /// A required key is absent.
+    pub const MISSING_KEY: &str = "E0103";
+    /// A value of the wrong type.
+    pub const TYPE_MISMATCH: &str = "E0104";
+    /// A value outside the parameter's allowed set.
+    pub const DISALLOWED_VALUE: &str = "E0105";
+    /// A non-repeatable directive used twice in the header block.
+    pub const DUPLICATE_DIRECTIVE: &str = "E0106";
+    /// A directive that must open the header block appears later in it.
+    pub const MISPLACED_DIRECTIVE: &str = "E0107";
+    /// Two directives that may not share a header block.
+    pub const CONFLICTING_DIRECTIVE: &str = "E0108";
+    /// A positional argument the directive has no parameter for.
+    pub const UNEXPECTED_ARGUMENT: &str = "E0109";
+    /// A key given twice in one directive.
+    pub const DUPLICATE_KEY: &str = "E0110";
+    /// An `option` or `multi_option` question without a `*` answer.
+    pub const NO_CORRECT_ANSWER: &str = "E0301";
+    /// An `option` question with more than one `*` answer.
+    pub const MULTIPLE_CORRECT: &str = "E0302";
+    /// The same choice, or pair side, listed twice.
+    pub const DUPLICATE_ANSWER: &str = "E0303";
+    /// Fewer answers than the question kind needs.
+    pub const TOO_FEW_ANSWERS: &str = "E0304";
+    /// A body line that does not belong in this question kind or position.
+    pub const UNEXPECTED_LINE: &str = "E0305";
+    /// Neither a `prompt=` key nor prompt text above the answers.
+    pub const MISSING_PROMPT: &str = "E0306";
+    /// A choice marker or pair side without text.
+    pub const EMPTY_ANSWER: &str = "E0307";
+    /// A document read as a question whose header names no question kind.
+    pub const NOT_A_QUESTION: &str = "E0308";
+    /// A `${name}` reference to a variable no `@define` declares.
+    pub const UNDEFINED_VARIABLE: &str = "E0401";
+    /// Variables whose definitions refer to each other in a cycle.
+    pub const CYCLIC_VARIABLE: &str = "E0402";
+    /// A variable defined more than once.
+    pub const DUPLICATE_VARIABLE: &str = "E0403";
+    /// An `@include` whose file cannot be read.
+    pub const INCLUDE_NOT_FOUND: &str = "E0501";
+    /// A file that includes itself, directly or through others.
+    pub const INCLUDE_CYCLE: &str = "E0502";
+    /// Includes nested deeper than [`crate::include::MAX_INCLUDE_DEPTH`].
+    pub const INCLUDE_DEPTH: &str = "E0503";
+    /// A directive that does not fit the type it is deserialized into.
+    pub const DESERIALIZE: &str = "E0201";
+}
+
+/// A secondary location a [`Diagnostic`] points at, such as the first use of
+/// a repeated key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Related {
+    pub span: Span,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+/// A located problem in a DSL source.
+///
+/// `span` is a byte range into the source; `line` and `column` are the
+/// 1-based position of its start (column counted in characters).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The file the span points into; only documents with includes have
+    /// more than the root file.
+    pub file: FileId,
+    pub span: Span,
+    pub line: usize,
+    pub column: usize,
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub expected: Vec<String>,
+    pub found: Option<String>,
+    pub help: Option<String>,
+    /// Other locations involved, in the same file.
+    pub related: Vec<Related>,
+}
+
+impl Diagnostic {
+    pub fn new(
+        src: &(impl Locate + ?Sized),
+        severity: Severity,
+        code: &'static str,
+        span: Span,
+        message: impl Into<String>,
+    ) -> Self {
+        let (line, column) = src.line_column(span.start);
+        Diagnostic {
+            file: FileId::default(),
+            span,
+            line,
+            column,
+            severity,
+            code,
+            message: message.into(),
+            expected: Vec::new(),
+            found: None,
+            help: None,
+            related: Vec::new(),
+        }
+    }
+
+    pub fn error(
+        src: &(impl Locate + ?Sized),
+        code: &'static str,
+        span: Span,
+        message: impl Into<String>,
+    ) -> Self {
+        Self::new(src, Severity::Error, code, span, message)
+    }
+
+    pub fn with_expected(mut self, expected: Vec<String>) -> Self {
+        self.expected = expected;
+        self
+    }
+
+    pub fn with_found(mut self, found: impl Into<String>) -> Self {
+        self.found = Some(found.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// Point at `span` in `src` too, labelled with `message`.
+    pub fn with_related(
+        mut self,
+        src: &(impl Locate + ?Sized),
+        span: Span,
+        message: impl Into<String>,
+    ) -> Self {
+        let (line, column) = src.line_column(span.start);
+        self.related.push(Related {
+            span,
+            line,
+            column,
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Move a diagnostic computed against a single line (so on line 1) to
+    /// `line`, whose first byte is at `offset` in the full source.
+    pub(crate) fn relocate(mut self, offset: usize, line: usize) -> Self {
+        self.span = self.span.start + offset..self.span.end + offset;
+        self.line = line;
+        for related in &mut self.related {
+            related.span = related.span.start + offset..related.span.end + offset;
+            related.line = line;
+        }
+        self
+    }
+
+    /// Move a diagnostic computed against a text that holds file `file`,
+    /// indexed by `index`, at offset `base` into that file.
+    pub(crate) fn rebase(mut self, file: FileId, index: &LineIndex, base: usize) -> Self {
+        let len = index.len();
+        let start = self.span.start - base;
+        self.span = start..(self.span.end - base).min(len);
+        (self.line, self.column) = index.line_column(start);
+        for related in &mut self.related {
+            let start = related.span.start.saturating_sub(base).min(len);
+            related.span = start..related.span.end.saturating_sub(base).min(len);
+            (related.line, related.column) = index.line_column(start);
+        }
+        self.file = file;
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Render in the style of rustc, with the offending line and carets
+    /// under the span, then each related location underlined with `-`.
+    /// `origin` names the source (usually a file path).
+    pub fn render(&self, src: &str, origin: &str) -> String {
+        let widest = self
+            .related
+            .iter()
+            .map(|r| r.line)
+            .fold(self.line, usize::max);
+        let gutter = " ".repeat(widest.to_string().len());
+        let label = if self.expected.is_empty() {
+            String::new()
+        } else {
+            format!("expected {}", self.expected.join(" or "))
+        };
+        let mut out = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+        out.push_str(&format!(
+            "{gutter}--> {origin}:{}:{}\n",
+            self.line, self.column
+        ));
+        out.push_str(&format!("{gutter} |\n"));
+        snippet(
+            &mut out,
+            src,
+            &gutter,
+            (&self.span, self.line, self.column),
+            '^',
+            &label,
+        );
+        for related in &self.related {
+            let at = (&related.span, related.line, related.column);
+            if related.line != self.line {
+                out.push_str(&format!("{gutter} |\n"));
+                snippet(&mut out, src, &gutter, at, '-', &related.message);
+            } else {
+                underline(&mut out, src, &gutter, at, '-', &related.message);
+            }
+        }
+        if let Some(help) = &self.help {
+            out.push_str(&format!("{gutter} |\n{gutter} = help: {help}\n"));
+        }
+        out
+    }
+}
+
+/// The text of the line holding byte `at`, and the byte offset it starts at.
+fn line_at(src: &str, at: usize) -> (usize, &str) {
+    let start = src[..at.min(src.len())].rfind('\n').map_or(0, |i| i + 1);
+    let text = src[start..].split('\n').next().unwrap_or("");
+    (start, text.trim_end_matches('\r'))
+}
+
+/// Write the source line of a span, numbered, with the span underlined.
+fn snippet(
+    out: &mut String,
+    src: &str,
+    gutter: &str,
+    at: (&Span, usize, usize),
+    mark: char,
+    label: &str,
+) {
+    let (_, text) = line_at(src, at.0.start);
+    out.push_str(&format!(
+        "{:>width$} | {text}\n",
+        at.1,
+        width = gutter.len()
+    ));
+    underline(out, src, gutter, at, mark, label);
+}
+
+/// Write `mark`s under a span, clipped to its line, followed by `label`.
+fn underline(
+    out: &mut String,
+    src: &str,
+    gutter: &str,
+    (span, _, column): (&Span, usize, usize),
+    mark: char,
+    label: &str,
+) {
+    let (start, text) = line_at(src, span.start);
+    let end = span.end.clamp(span.start, start + text.len());
+    let marks = src
+        .get(span.start.min(end)..end)
+        .map_or(0, |s| s.chars().count())
+        .max(1);
+    let label = if label.is_empty() {
+        String::new()
+    } else {
+        format!(" {label}")
+    };
+    out.push_str(&format!(
+        "{gutter} | {}{}{label}\n",
+        " ".repeat(column - 1),
+        mark.to_string().repeat(marks)
+    ));
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}[{}]: {}",
+            self.line, self.column, self.severity, self.code, self.message
+        )
+    }
+}
+
+/// A text diagnostics are located in.
+///
+/// A `str` is indexed on every call, which suits a single line; pass a
+/// whole document as a [`LineIndex`] built once, or every diagnostic rescans
+/// it.
+pub trait Locate {
+    /// 1-based line and character column of byte `offset`.
+    fn line_column(&self, offset: usize) -> (usize, usize);
+}
+
+impl Locate for LineIndex {
+    fn line_column(&self, offset: usize) -> (usize, usize) {
+        let pos = self.line_col(offset, Encoding::Char);
+        (pos.line as usize + 1, pos.col as usize + 1)
+    }
+}
+
+impl Locate for str {
+    fn line_column(&self, offset: usize) -> (usize, usize) {
+        LineIndex::new(self).line_column(offset)
+    }
+}
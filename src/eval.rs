@@ -0,0 +1,355 @@
+//! Variables and `${name}` interpolation.
+//!
+//! `@define course=CS101 points=3` defines variables for the whole document,
+//! wherever it appears; `${course}` inside a pair value or a body text line
+//! refers to one, and definitions may refer to each other. [`evaluate`] runs
+//! after parsing and returns the document with every reference resolved. A
+//! value that is nothing but `${name}` takes the variable's value, type
+//! included, so `points=${points}` is an integer; anywhere else the value is
+//! spliced into the surrounding text. `$${` stands for a literal `${`.
+
+use std::collections::HashMap;
+
+use crate::diagnostic::{Diagnostic, codes};
+use crate::line_index::{Encoding, LineIndex};
+use crate::parser::{BodyKind, Directive, Document, Span};
+use crate::schema::suggest;
+use crate::value::Value;
+
+/// Name of the directive that defines variables.
+pub const DEFINE: &str = "define";
+
+/// How a literal `${` is written.
+pub(crate) const ESCAPE: &str = "$${";
+
+/// `text` with each escaped `$${` turned back into `${`.
+fn unescape(text: &str) -> String {
+    text.replace(ESCAPE, "${")
+}
+
+/// The `${name}` references in `text`: the byte range of each and the name.
+/// `${` not followed by an identifier and `}` is plain text, and so is the
+/// `${` of an escaped `$${`.
+pub(crate) fn interpolations(text: &str) -> impl Iterator<Item = (Span, &str)> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        while let Some(found) = text[pos..].find('$') {
+            let start = pos + found;
+            if text[start..].starts_with(ESCAPE) {
+                pos = start + ESCAPE.len();
+                continue;
+            }
+            if !text[start..].starts_with("${") {
+                pos = start + 1;
+                continue;
+            }
+            let name_start = start + 2;
+            let name_len = text[name_start..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(text.len() - name_start);
+            let name = &text[name_start..name_start + name_len];
+            let end = name_start + name_len;
+            let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && text[end..].starts_with('}');
+            if valid {
+                pos = end + 1;
+                return Some((start..end + 1, name));
+            }
+            pos = name_start;
+        }
+        None
+    })
+}
+
+/// Resolve every variable reference in `doc`, returning the evaluated
+/// document. Spans are unchanged, so they still point into `src`.
+///
+/// Undefined references and cycles are reported and left as written; a
+/// variable defined twice keeps its first definition.
+pub fn evaluate(src: &str, doc: &Document) -> (Document, Vec<Diagnostic>) {
+    let mut scope = Scope {
+        src,
+        index: LineIndex::new(src),
+        definitions: HashMap::new(),
+        resolved: HashMap::new(),
+        diagnostics: Vec::new(),
+    };
+    let directives =
+        doc.headers
+            .iter()
+            .chain(doc.body.iter().filter_map(|node| match &node.kind {
+                BodyKind::Directive(d) => Some(d),
+                _ => None,
+            }));
+    for directive in directives.filter(|d| d.name == DEFINE) {
+        scope.define(directive);
+    }
+    let mut names: Vec<&str> = scope.definitions.keys().map(String::as_str).collect();
+    names.sort_by_key(|name| scope.definitions[*name].value_span.start);
+    let names: Vec<String> = names.into_iter().map(String::from).collect();
+    for name in &names {
+        scope.resolve(name, &mut Vec::new());
+    }
+
+    let mut out = doc.clone();
+    for directive in &mut out.headers {
+        scope.directive(directive);
+    }
+    for node in &mut out.body {
+        match &mut node.kind {
+            BodyKind::Directive(directive) => scope.directive(directive),
+            BodyKind::Text(text) => {
+                scope.check(node.span.clone());
+                *text = scope.splice(text, &mut Vec::new());
+            }
+            BodyKind::Blank => {}
+        }
+    }
+    (out, scope.diagnostics)
+}
+
+struct Definition {
+    value: Value,
+    key_span: Span,
+    value_span: Span,
+}
+
+struct Scope<'a> {
+    src: &'a str,
+    index: LineIndex,
+    definitions: HashMap<String, Definition>,
+    /// Values of the variables resolved so far.
+    resolved: HashMap<String, Value>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Scope<'_> {
+    fn line_of(&self, span: &Span) -> u32 {
+        self.index.line_col(span.start, Encoding::Char).line + 1
+    }
+
+    fn define(&mut self, directive: &Directive) {
+        for pair in &directive.pairs {
+            if let Some(first) = self.definitions.get(&pair.key) {
+                let line = self.line_of(&first.key_span);
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        &self.index,
+                        codes::DUPLICATE_VARIABLE,
+                        pair.key_span.clone(),
+                        format!("variable `{}` is already defined", pair.key),
+                    )
+                    .with_help(format!("first defined on line {line}")),
+                );
+                continue;
+            }
+            self.definitions.insert(
+                pair.key.clone(),
+                Definition {
+                    value: pair.value.clone(),
+                    key_span: pair.key_span.clone(),
+                    value_span: pair.value_span.clone(),
+                },
+            );
+        }
+    }
+
+    /// The value of variable `name`, or `None` if it is undefined or part of
+    /// a cycle. `stack` holds the variables being resolved.
+    fn resolve(&mut self, name: &str, stack: &mut Vec<String>) -> Option<Value> {
+        if let Some(value) = self.resolved.get(name) {
+            return Some(value.clone());
+        }
+        let definition = self.definitions.get(name)?;
+        if let Some(at) = stack.iter().position(|s| s == name) {
+            // The innermost variable refers back to one being resolved.
+            let closing = &self.definitions[stack.last().expect("non-empty stack")];
+            let path: Vec<&str> = stack[at..]
+                .iter()
+                .map(String::as_str)
+                .chain([name])
+                .collect();
+            self.diagnostics.push(
+                Diagnostic::error(
+                    &self.index,
+                    codes::CYCLIC_VARIABLE,
+                    closing.value_span.clone(),
+                    format!("variable `{name}` is defined in terms of itself"),
+                )
+                .with_help(format!("cycle: {}", path.join(" -> "))),
+            );
+            return None;
+        }
+        let value = definition.value.clone();
+        stack.push(name.to_string());
+        let value = self.interpolate(value, stack);
+        stack.pop();
+        self.resolved.insert(name.to_string(), value.clone());
+        Some(value)
+    }
+
+    fn interpolate(&mut self, value: Value, stack: &mut Vec<String>) -> Value {
+        match value {
+            Value::String(s) => {
+                let whole = interpolations(&s)
+                    .next()
+                    .filter(|(span, _)| *span == (0..s.len()))
+                    .map(|(_, name)| name.to_string());
+                match whole {
+                    Some(name) => self.resolve(&name, stack).unwrap_or(Value::String(s)),
+                    None => Value::String(self.splice(&s, stack)),
+                }
+            }
+            Value::List(items) => Value::List(
+                items
+                    .into_iter()
+                    .map(|v| self.interpolate(v, stack))
+                    .collect(),
+            ),
+            Value::Map(entries) => Value::Map(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k, self.interpolate(v, stack)))
+                    .collect(),
+            ),
+            other => other,
+        }
+    }
+
+    /// `text` with each resolvable reference replaced by its value's text and
+    /// escapes undone.
+    fn splice(&mut self, text: &str, stack: &mut Vec<String>) -> String {
+        let mut out = String::new();
+        let mut last = 0;
+        let references: Vec<(Span, String)> = interpolations(text)
+            .map(|(span, name)| (span, name.to_string()))
+            .collect();
+        for (span, name) in references {
+            let Some(value) = self.resolve(&name, stack) else {
+                continue;
+            };
+            out.push_str(&unescape(&text[last..span.start]));
+            match value {
+                Value::String(s) => out.push_str(&s),
+                other => out.push_str(&other.to_string()),
+            }
+            last = span.end;
+        }
+        out.push_str(&unescape(&text[last..]));
+        out
+    }
+
+    fn directive(&mut self, directive: &mut Directive) {
+        for arg in &mut directive.args {
+            self.check(arg.span.clone());
+            arg.value = self.interpolate(arg.value.clone(), &mut Vec::new());
+        }
+        for pair in &mut directive.pairs {
+            self.check(pair.value_span.clone());
+            pair.value = self.interpolate(pair.value.clone(), &mut Vec::new());
+        }
+    }
+
+    /// Report the undefined references in the source text at `span`.
+    fn check(&mut self, span: Span) {
+        let text = &self.src[span.clone()];
+        for (at, name) in interpolations(text) {
+            if self.definitions.contains_key(name) {
+                continue;
+            }
+            let at = span.start + at.start..span.start + at.end;
+            let help = match suggest(name, self.definitions.keys().map(String::as_str)) {
+                Some(known) => format!("did you mean `${{{known}}}`?"),
+                None => format!("define it with `@{DEFINE} {name}=...`"),
+            };
+            self.diagnostics.push(
+                Diagnostic::error(
+                    &self.index,
+                    codes::UNDEFINED_VARIABLE,
+                    at,
+                    format!("undefined variable `{name}`"),
+                )
+                .with_help(help),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_document;
+
+    fn eval(src: &str) -> (Document, Vec<(&'static str, String, &str)>) {
+        let (doc, diagnostics) = evaluate(src, &parse_document(src).unwrap());
+        let summary = diagnostics
+            .into_iter()
+            .map(|d| (d.code, d.help.unwrap_or_default(), &src[d.span]))
+            .collect();
+        (doc, summary)
+    }
+
+    #[test]
+    fn resolves_references() {
+        let src = "@option prompt=\"${course}: ${title}\" points=${points}\n\
+                   @define course=CS101 title='Week ${week}' week=3\n\
+                   @meta tags=[${course}, x${week}] author=${who}\n\
+                   Welcome to ${course}, $5 ${not closed $${week}\n\
+                   @define points=${week} who={name=${course}}\n";
+        let (doc, diagnostics) = eval(src);
+        assert_eq!(diagnostics, []);
+        let header = &doc.headers[0];
+        assert_eq!(header.pairs[0].value, "CS101: Week 3".into());
+        assert_eq!(header.pairs[1].value, Value::Int(3));
+        assert_eq!(&src[header.pairs[1].value_span.clone()], "${points}");
+        assert_eq!(
+            doc.headers[2].pairs[0].value,
+            Value::List(vec!["CS101".into(), "x3".into()])
+        );
+        assert_eq!(
+            doc.headers[2].pairs[1].value,
+            Value::Map(vec![("name".into(), "CS101".into())])
+        );
+        assert!(
+            matches!(&doc.body[0].kind, BodyKind::Text(t) if t == "Welcome to CS101, $5 ${not closed ${week}")
+        );
+    }
+
+    #[test]
+    fn reports_undefined_cyclic_and_duplicate() {
+        let src = "@option prompt=${corse} points=${a}\n\
+                   @define course=x a=${b} b='${c}!' c=${a} d=${d}\n\
+                   Q ${nope}\n\
+                   @define course=y\n";
+        let (doc, diagnostics) = eval(src);
+        assert_eq!(
+            diagnostics,
+            [
+                (
+                    codes::DUPLICATE_VARIABLE,
+                    "first defined on line 2".to_string(),
+                    "course"
+                ),
+                (
+                    codes::CYCLIC_VARIABLE,
+                    "cycle: a -> b -> c -> a".to_string(),
+                    "${a}"
+                ),
+                (codes::CYCLIC_VARIABLE, "cycle: d -> d".to_string(), "${d}"),
+                (
+                    codes::UNDEFINED_VARIABLE,
+                    "did you mean `${course}`?".to_string(),
+                    "${corse}"
+                ),
+                (
+                    codes::UNDEFINED_VARIABLE,
+                    "define it with `@define nope=...`".to_string(),
+                    "${nope}"
+                ),
+            ]
+        );
+        // Unresolvable references stay as written.
+        assert_eq!(doc.headers[0].pairs[0].value, "${corse}".into());
+        assert_eq!(doc.headers[1].pairs[2].value, "${a}!".into());
+    }
+}
@@ -0,0 +1,316 @@
+//! Canonical pretty-printer.
+//!
+//! Directive lines are re-printed from their parsed form (`@name arg key=value`,
+//! values in [`Value`](crate::value::Value) canonical spelling); text lines
+//! lose surrounding whitespace; runs of blank lines collapse to one. Lines
+//! that do not parse are kept as written so formatting never loses text.
+
+use crate::parser::{Directive, parse_directive};
+use crate::schema::schema_for;
+use crate::syntax::{SyntaxKind, SyntaxNode, parse_syntax};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyOrder {
+    /// Keep keys in the order they were written.
+    #[default]
+    Preserve,
+    /// Sort keys by name.
+    Alphabetical,
+    /// Order keys as the directive's schema declares them; unknown keys last.
+    Schema,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+    /// Use whichever ending the first line of the input has.
+    Auto,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FormatOptions {
+    pub key_order: KeyOrder,
+    /// Pad consecutive directive lines so their `=` signs line up column by column.
+    pub align_equals: bool,
+    pub line_ending: LineEnding,
+}
+
+enum Line {
+    Blank,
+    Text(String),
+    Directive {
+        /// `@name` followed by the positional arguments.
+        head: String,
+        pairs: Vec<(String, String)>,
+        comment: Option<String>,
+    },
+}
+
+/// Format with the default options.
+pub fn format(src: &str) -> String {
+    format_with(src, &FormatOptions::default())
+}
+
+pub fn format_with(src: &str, options: &FormatOptions) -> String {
+    let newline = match options.line_ending {
+        LineEnding::Lf => "\n",
+        LineEnding::CrLf => "\r\n",
+        LineEnding::Auto => match src.find('\n') {
+            Some(i) if src[..i].ends_with('\r') => "\r\n",
+            _ => "\n",
+        },
+    };
+    let mut lines: Vec<Line> = Vec::new();
+    for node in parse_syntax(src).children() {
+        let line = match node.kind() {
+            SyntaxKind::BlankLine => Line::Blank,
+            SyntaxKind::Directive => directive_line(&node, options.key_order),
+            _ => Line::Text(line_text(&node)),
+        };
+        let repeated_blank =
+            matches!(line, Line::Blank) && matches!(lines.last(), None | Some(Line::Blank));
+        if !repeated_blank {
+            lines.push(line);
+        }
+    }
+    if matches!(lines.last(), Some(Line::Blank)) {
+        lines.pop();
+    }
+
+    let mut out = String::new();
+    let mut start = 0;
+    while start < lines.len() {
+        let run = lines[start..]
+            .iter()
+            .take_while(|l| matches!(l, Line::Directive { .. }))
+            .count();
+        if run == 0 {
+            if let Line::Text(text) = &lines[start] {
+                out.push_str(text);
+            }
+            out.push_str(newline);
+            start += 1;
+            continue;
+        }
+        let block = &lines[start..start + run];
+        let widths = options.align_equals.then(|| column_widths(block));
+        for line in block {
+            out.push_str(&render_directive(line, widths.as_ref()));
+            out.push_str(newline);
+        }
+        start += run;
+    }
+    out
+}
+
+/// The line's text without indentation, trailing whitespace or terminator.
+fn line_text(node: &SyntaxNode) -> String {
+    node.text().to_string().trim().to_string()
+}
+
+fn directive_line(node: &SyntaxNode, order: KeyOrder) -> Line {
+    let text = line_text(node);
+    let Some(Directive {
+        name, args, pairs, ..
+    }) = parse_directive(&text)
+    else {
+        return Line::Text(text);
+    };
+    let comment = node
+        .children_with_tokens()
+        .filter_map(|e| e.into_token())
+        .find(|t| t.kind() == SyntaxKind::Comment)
+        .map(|t| t.text().trim_end().to_string());
+    let mut pairs: Vec<(String, String)> = pairs
+        .into_iter()
+        .map(|p| (p.key, p.value.to_string()))
+        .collect();
+    match order {
+        KeyOrder::Preserve => {}
+        KeyOrder::Alphabetical => pairs.sort_by(|a, b| a.0.cmp(&b.0)),
+        KeyOrder::Schema => {
+            let rank = |key: &str| {
+                schema_for(&name)
+                    .and_then(|s| s.params.iter().position(|p| p.name == key))
+                    .unwrap_or(usize::MAX)
+            };
+            pairs.sort_by_key(|(key, _)| rank(key));
+        }
+    }
+    let head = std::iter::once(format!("@{name}"))
+        .chain(args.iter().map(|a| a.value.to_string()))
+        .collect::<Vec<_>>()
+        .join(" ");
+    Line::Directive {
+        head,
+        pairs,
+        comment,
+    }
+}
+
+struct Widths {
+    head: usize,
+    keys: Vec<usize>,
+    values: Vec<usize>,
+}
+
+fn column_widths(block: &[Line]) -> Widths {
+    let mut widths = Widths {
+        head: 0,
+        keys: Vec::new(),
+        values: Vec::new(),
+    };
+    for line in block {
+        let Line::Directive { head, pairs, .. } = line else {
+            continue;
+        };
+        widths.head = widths.head.max(head.chars().count());
+        for (i, (key, value)) in pairs.iter().enumerate() {
+            if widths.keys.len() <= i {
+                widths.keys.push(0);
+                widths.values.push(0);
+            }
+            widths.keys[i] = widths.keys[i].max(key.chars().count());
+            widths.values[i] = widths.values[i].max(value.chars().count());
+        }
+    }
+    widths
+}
+
+fn pad(out: &mut String, text: &str, width: usize) {
+    out.push_str(text);
+    out.extend(std::iter::repeat_n(
+        ' ',
+        width.saturating_sub(text.chars().count()),
+    ));
+}
+
+fn render_directive(line: &Line, widths: Option<&Widths>) -> String {
+    let Line::Directive {
+        head,
+        pairs,
+        comment,
+    } = line
+    else {
+        return String::new();
+    };
+    let mut out = head.clone();
+    render_pairs(&mut out, head, pairs, widths);
+    if let Some(comment) = comment {
+        out.push(' ');
+        out.push_str(comment);
+    }
+    out
+}
+
+fn render_pairs(out: &mut String, head: &str, pairs: &[(String, String)], widths: Option<&Widths>) {
+    if pairs.is_empty() {
+        return;
+    }
+    out.push(' ');
+    if let Some(w) = widths {
+        pad(out, "", w.head - head.chars().count());
+    }
+    for (i, (key, value)) in pairs.iter().enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        match widths {
+            Some(w) => {
+                pad(out, key, w.keys[i]);
+                out.push('=');
+                if i + 1 < pairs.len() {
+                    pad(out, value, w.values[i]);
+                } else {
+                    out.push_str(value);
+                }
+            }
+            None => {
+                out.push_str(key);
+                out.push('=');
+                out.push_str(value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_document;
+
+    const MESSY: &str = "\n\n  @option   shuffle = true  points=2 prompt='Pick one'  \r\n\
+                         @matching_pair count=3 points=10\n\n\n   What is 2+2?   \n\
+                         @bad key=\n\n";
+
+    #[test]
+    fn canonical_output() {
+        assert_eq!(
+            format(MESSY),
+            "@option shuffle=true points=2 prompt=\"Pick one\"\n\
+             @matching_pair count=3 points=10\n\
+             \n\
+             What is 2+2?\n\
+             @bad key=\n"
+        );
+        assert_eq!(
+            format("@option   'What is 2+2?'  points=1 'a=b'\n"),
+            "@option \"What is 2+2?\" \"a=b\" points=1\n"
+        );
+        let options = FormatOptions {
+            key_order: KeyOrder::Schema,
+            align_equals: true,
+            line_ending: LineEnding::Auto,
+        };
+        assert_eq!(
+            format_with(
+                "@option shuffle=true points=2 prompt='Pick one'\r\n@matching_pair count=3 points=10\n",
+                &options
+            ),
+            "@option        prompt=\"Pick one\" points=2 shuffle=true\r\n\
+             @matching_pair points=10         count =3\r\n"
+        );
+    }
+
+    #[test]
+    fn idempotent_and_meaning_preserving() {
+        let all = [
+            FormatOptions::default(),
+            FormatOptions {
+                key_order: KeyOrder::Alphabetical,
+                align_equals: true,
+                line_ending: LineEnding::CrLf,
+            },
+        ];
+        for options in all {
+            let once = format_with(MESSY, &options);
+            assert_eq!(format_with(&once, &options), once);
+        }
+        let src = "@option a=[1,2.50, 'x y'] b={k = v}\ntext\n";
+        let formatted = format(src);
+        assert_eq!(formatted, "@option a=[1, 2.5, \"x y\"] b={k=v}\ntext\n");
+        assert_eq!(format("@meta author=1e400\n"), "@meta author=1e400\n");
+        // `#` after whitespace starts a comment even inside a list.
+        for src in ["@meta tags=[a, #1]\n", "@meta tags=[a,#1] # c\n"] {
+            let once = format(src);
+            assert_eq!(format(&once), once, "not idempotent for {src:?}");
+        }
+        assert_eq!(format("@meta tags=[a, #1]\n"), "@meta tags=[a, #1]\n");
+        assert_eq!(
+            format("@meta tags=[a,#1] # c\n"),
+            "@meta tags=[a, \"#1\"] # c\n"
+        );
+        assert_eq!(
+            format("  # why\n@option   x=1   # note \n@bad x=  // keep\n"),
+            "# why\n@option x=1 # note\n@bad x=  // keep\n"
+        );
+        let values = |src: &str| {
+            let doc = parse_document(src).unwrap();
+            let header = doc.headers[0].pairs.iter().map(|p| p.value.clone());
+            (header.collect::<Vec<_>>(), doc.body.len())
+        };
+        assert_eq!(values(src), values(&formatted));
+    }
+}
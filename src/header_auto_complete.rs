@@ -76,15 +76,7 @@ fn show_overlay(header_line: &HtmlElement, filter: &str) {
         .and_then(|n| n.dyn_ref::<HtmlElement>().map(|h| h.clone()));
     let rect = code_span.as_ref().map(|c| c.get_bounding_client_rect());
     // Pre-compute matches
-    let matches: Vec<&'static str> = Import::VARIANTS
-        .iter()
-        .filter(|k| k.to_lowercase().starts_with(&filter.to_lowercase()))
-        .copied()
-        .collect();
-    let lower_filter = filter.to_lowercase();
-    let exact_match = Import::VARIANTS
-        .iter()
-        .any(|k| k.to_lowercase() == lower_filter);
+    let (matches, exact_match) = import_matches(filter);
     if matches.is_empty() {
         if let Some(ex) = doc.get_element_by_id(OVERLAY_ID) {
             if let Some(html) = ex.dyn_ref::<HtmlElement>() {
@@ -152,6 +144,21 @@ fn show_overlay(header_line: &HtmlElement, filter: &str) {
     }
 }
 
+/// The question types starting with `filter`, ignoring case, and whether one
+/// equals it. These are the directive names, as in `@multi_option`.
+fn import_matches(filter: &str) -> (Vec<&'static str>, bool) {
+    let lower_filter = filter.to_lowercase();
+    let matches = Import::VARIANTS
+        .iter()
+        .filter(|k| k.to_lowercase().starts_with(&lower_filter))
+        .copied()
+        .collect();
+    let exact_match = Import::VARIANTS
+        .iter()
+        .any(|k| k.to_lowercase() == lower_filter);
+    (matches, exact_match)
+}
+
 fn update_overlay_items_with_matches(overlay: &Element, matches: &[&'static str]) {
     while let Some(child) = overlay.first_child() {
         let _ = overlay.remove_child(&child);
@@ -361,3 +368,20 @@ fn apply_code_span_style(code: &HtmlElement, exact: bool, no_matches: bool) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offers_directive_names() {
+        assert_eq!(
+            import_matches(""),
+            (vec!["option", "multi_option", "matching_pair"], false)
+        );
+        assert_eq!(import_matches("Multi_"), (vec!["multi_option"], false));
+        assert_eq!(import_matches("option"), (vec!["option"], true));
+        // The names before they followed the directives no longer match.
+        assert_eq!(import_matches("MultiOption"), (vec![], false));
+    }
+}
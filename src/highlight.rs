@@ -0,0 +1,221 @@
+//! Syntax highlighting for terminals and HTML.
+//!
+//! [`highlight`] runs the lexer behind
+//! [`highlight_first_line_json`](crate::parser::highlight_first_line_json)
+//! over every line of a document. [`to_ansi`] renders the result with
+//! terminal colors, [`to_html`] as a `<pre>` fragment with one CSS class per
+//! token kind, styled by [`STYLESHEET`].
+
+use serde::Serialize;
+
+use crate::eval::interpolations;
+use crate::parser::{Span, highlight_line, is_comment_line, lines_with_offsets};
+
+/// A token to color.
+///
+/// `kind` is one of the lexer's token kinds (`At`, `Ident` for keys, `Equals`,
+/// `String`, `Number`, `Bool`, `Value`, `LBracket`, `RBracket`, `LBrace`,
+/// `RBrace`, `Comma`, `Comment`, `Interpolation`, `Error`), `Directive` for a
+/// directive's name, or `Text` for a body text line. Whitespace has no token.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Highlight {
+    pub kind: &'static str,
+    /// Byte range in the source.
+    pub span: Span,
+}
+
+/// The tokens of every line of `src`, in source order.
+pub fn highlight(src: &str) -> Vec<Highlight> {
+    let mut out = Vec::new();
+    for (offset, line) in lines_with_offsets(src) {
+        let trimmed = line.trim();
+        let start = offset + line.len() - line.trim_start().len();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if !trimmed.starts_with('@') && !is_comment_line(trimmed) {
+            let mut last = 0;
+            for (span, _) in interpolations(trimmed) {
+                if span.start > last {
+                    out.push(token("Text", start + last..start + span.start));
+                }
+                out.push(token("Interpolation", start + span.start..start + span.end));
+                last = span.end;
+            }
+            if last < trimmed.len() {
+                out.push(token("Text", start + last..start + trimmed.len()));
+            }
+            continue;
+        }
+        let tokens: Vec<_> = highlight_line(line)
+            .into_iter()
+            .filter(|t| t.kind != "Ws")
+            .collect();
+        for (i, t) in tokens.iter().enumerate() {
+            let kind = match t.kind {
+                // The lexer does not tell names, keys and bare values apart.
+                "Ident" if i > 0 && tokens[i - 1].kind == "At" => "Directive",
+                "Ident" if tokens.get(i + 1).is_none_or(|next| next.kind != "Equals") => "Value",
+                kind => kind,
+            };
+            out.push(token(kind, offset + t.start..offset + t.end));
+        }
+    }
+    out
+}
+
+fn token(kind: &'static str, span: Span) -> Highlight {
+    Highlight { kind, span }
+}
+
+/// The CSS class, without the `q-` prefix, that [`to_html`] gives tokens of
+/// `kind`. The brackets and commas share `punctuation`.
+pub fn css_class(kind: &str) -> &'static str {
+    match kind {
+        "At" | "Directive" => "directive",
+        "Ident" => "key",
+        "Equals" => "operator",
+        "String" => "string",
+        "Number" => "number",
+        "Bool" => "boolean",
+        "Value" => "value",
+        "LBracket" | "RBracket" | "LBrace" | "RBrace" | "Comma" => "punctuation",
+        "Comment" => "comment",
+        "Interpolation" => "variable",
+        "Error" => "error",
+        _ => "text",
+    }
+}
+
+/// The SGR parameters for a class; `None` leaves the token uncolored.
+fn ansi_style(class: &str) -> Option<&'static str> {
+    Some(match class {
+        "directive" => "1;35",
+        "key" => "36",
+        "string" => "32",
+        "number" | "boolean" => "33",
+        "value" => "34",
+        "comment" => "90",
+        "variable" => "1;36",
+        "error" => "4;31",
+        _ => return None,
+    })
+}
+
+/// `src` with ANSI color escapes around its tokens.
+pub fn to_ansi(src: &str) -> String {
+    render(src, |out, kind, text| match ansi_style(css_class(kind)) {
+        Some(style) => out.push_str(&format!("\x1b[{style}m{text}\x1b[0m")),
+        None => out.push_str(text),
+    })
+}
+
+/// `src` as `<pre class="q-highlight">`, each token in a `<span>` with a
+/// `q-` class from [`css_class`].
+pub fn to_html(src: &str) -> String {
+    let mut out = String::from("<pre class=\"q-highlight\"><code>");
+    out.push_str(&render(src, |out, kind, text| match kind {
+        "" => out.push_str(&escape(text)),
+        kind => out.push_str(&format!(
+            "<span class=\"q-{}\">{}</span>",
+            css_class(kind),
+            escape(text)
+        )),
+    }));
+    out.push_str("</code></pre>\n");
+    out
+}
+
+/// Styles for the classes of [`to_html`].
+pub const STYLESHEET: &str = "\
+.q-highlight .q-directive { color: #a626a4; font-weight: bold; }
+.q-highlight .q-key { color: #0184bc; }
+.q-highlight .q-string { color: #50a14f; }
+.q-highlight .q-number, .q-highlight .q-boolean { color: #986801; }
+.q-highlight .q-value { color: #4078f2; }
+.q-highlight .q-comment { color: #a0a1a7; font-style: italic; }
+.q-highlight .q-variable { color: #0184bc; font-weight: bold; }
+.q-highlight .q-error { color: #e45649; text-decoration: wavy underline; }
+";
+
+/// Write `src` through `emit`, called with each token's kind and text and
+/// with an empty kind for the text between tokens.
+fn render(src: &str, mut emit: impl FnMut(&mut String, &str, &str)) -> String {
+    let mut out = String::with_capacity(src.len());
+    let mut last = 0;
+    for token in highlight(src) {
+        if token.span.start > last {
+            emit(&mut out, "", &src[last..token.span.start]);
+        }
+        emit(&mut out, token.kind, &src[token.span.clone()]);
+        last = token.span.end;
+    }
+    if last < src.len() {
+        emit(&mut out, "", &src[last..]);
+    }
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_every_line() {
+        let src = "# draft\n@option \"Pilih\" points=2 tags=[a, ${x}]\n\n${x} < 3?\n* ya\n@x =\n";
+        let tokens: Vec<(&str, &str)> = highlight(src)
+            .iter()
+            .map(|t| (t.kind, &src[t.span.clone()]))
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                ("Comment", "# draft"),
+                ("At", "@"),
+                ("Directive", "option"),
+                ("String", "\"Pilih\""),
+                ("Ident", "points"),
+                ("Equals", "="),
+                ("Number", "2"),
+                ("Ident", "tags"),
+                ("Equals", "="),
+                ("LBracket", "["),
+                ("Value", "a"),
+                ("Comma", ","),
+                ("Interpolation", "${x}"),
+                ("RBracket", "]"),
+                ("Interpolation", "${x}"),
+                ("Text", " < 3?"),
+                ("Text", "* ya"),
+                ("At", "@"),
+                ("Directive", "x"),
+                ("Error", "="),
+            ]
+        );
+    }
+
+    #[test]
+    fn renders_ansi_and_html() {
+        let src = "@meta author=\"A & B\"\nWho?\n";
+        assert_eq!(
+            to_ansi(src),
+            "\x1b[1;35m@\x1b[0m\x1b[1;35mmeta\x1b[0m \x1b[36mauthor\x1b[0m=\
+             \x1b[32m\"A & B\"\x1b[0m\nWho?\n"
+        );
+        assert_eq!(
+            to_html(src),
+            "<pre class=\"q-highlight\"><code><span class=\"q-directive\">@</span>\
+             <span class=\"q-directive\">meta</span> <span class=\"q-key\">author</span>\
+             <span class=\"q-operator\">=</span>\
+             <span class=\"q-string\">&quot;A &amp; B&quot;</span>\n\
+             <span class=\"q-text\">Who?</span>\n</code></pre>\n"
+        );
+    }
+}
@@ -2,6 +2,8 @@ use serde::{Deserialize, Serialize};
 use strum::VariantNames;
 
 #[derive(Debug, Clone, Serialize, Deserialize, VariantNames)]
+// `VARIANTS` spell the directive names, `multi_option` rather than `MultiOption`.
+#[strum(serialize_all = "snake_case")]
 pub enum Import {
     #[serde(rename = "option")]
     Option(String),
@@ -0,0 +1,459 @@
+//! `@include path=...` over a pluggable file system.
+//!
+//! [`parse_with_includes`] replaces every `@include` line with the lines of
+//! the file it names, read through a [`FileProvider`]: [`MemoryProvider`] in
+//! the browser, [`FsProvider`] in native builds.
+//!
+//! Every file loaded gets a [`FileId`] and its own range in a [`SourceMap`],
+//! so a span in the combined [`Document`] identifies the fragment it came
+//! from. Diagnostics are reported against their fragment: their `file` names
+//! it and their span, line and column are relative to its text.
+
+use std::collections::HashMap;
+use std::io;
+
+use crate::diagnostic::{Diagnostic, codes};
+use crate::eval::evaluate;
+use crate::line_index::LineIndex;
+use crate::parser::{Assembler, Directive, Document, LineParse, LineParser, Section};
+use crate::parser::{Span, lines_with_offsets};
+use crate::schema::{ValidateOptions, bind_args, validate_directive, validate_document_with};
+use crate::value::Value;
+
+/// Name of the directive that includes another file.
+pub const INCLUDE: &str = "include";
+
+/// How deeply includes may nest.
+pub const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Reads the files `@include` names.
+pub trait FileProvider {
+    /// The text of the file at `path`, as returned by [`FileProvider::resolve`].
+    fn read(&self, path: &str) -> io::Result<String>;
+
+    /// The path of `include`, written in file `from`. By default relative
+    /// paths are resolved against the directory of `from`, with `/` as the
+    /// separator and `.` and `..` segments removed.
+    fn resolve(&self, from: &str, include: &str) -> String {
+        let dir = match from.rfind('/') {
+            Some(i) if !include.starts_with('/') => &from[..=i],
+            _ => "",
+        };
+        normalize(&format!("{dir}{include}"))
+    }
+}
+
+fn normalize(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "." => {}
+            ".." if parts.last().is_some_and(|p| !p.is_empty() && *p != "..") => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// Files held in memory, keyed by path.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryProvider {
+    files: HashMap<String, String>,
+}
+
+impl MemoryProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, path: impl Into<String>, text: impl Into<String>) {
+        self.files.insert(path.into(), text.into());
+    }
+}
+
+impl FileProvider for MemoryProvider {
+    fn read(&self, path: &str) -> io::Result<String> {
+        self.files
+            .get(path)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file"))
+    }
+}
+
+/// Files on disk, with paths relative to the working directory.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FsProvider;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileProvider for FsProvider {
+    fn read(&self, path: &str) -> io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+}
+
+/// Identifies a file in a [`SourceMap`]. The root file is `FileId(0)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct FileId(pub u32);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    pub path: String,
+    pub text: String,
+    pub index: LineIndex,
+    /// Where the file's text starts in [`SourceMap::text`].
+    pub base: usize,
+}
+
+/// The files of a document, laid out one after another so that every byte
+/// of every file has its own offset.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+    text: String,
+}
+
+impl SourceMap {
+    fn add(&mut self, path: String, text: String) -> FileId {
+        let id = FileId(self.files.len() as u32);
+        let base = self.text.len();
+        self.text.push_str(&text);
+        // Keep the next file's first line separate.
+        if !text.is_empty() && !text.ends_with('\n') {
+            self.text.push('\n');
+        }
+        let index = LineIndex::new(&text);
+        self.files.push(SourceFile {
+            path,
+            text,
+            index,
+            base,
+        });
+        id
+    }
+
+    pub fn file(&self, id: FileId) -> &SourceFile {
+        &self.files[id.0 as usize]
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = (FileId, &SourceFile)> {
+        self.files
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (FileId(i as u32), f))
+    }
+
+    /// Every file's text at its base offset; the text the spans of a combined
+    /// document point into.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The file containing combined `offset`.
+    pub fn file_at(&self, offset: usize) -> FileId {
+        let i = self.files.partition_point(|f| f.base <= offset);
+        FileId(i.saturating_sub(1) as u32)
+    }
+
+    /// `span` in the combined text as a span in its file.
+    pub fn locate(&self, span: &Span) -> (FileId, Span) {
+        let id = self.file_at(span.start);
+        let file = self.file(id);
+        let end = (span.end - file.base).min(file.text.len());
+        (id, span.start - file.base..end)
+    }
+
+    /// Move a diagnostic computed against [`SourceMap::text`] into its file.
+    pub fn localize(&self, diagnostic: Diagnostic) -> Diagnostic {
+        let (id, _) = self.locate(&diagnostic.span);
+        let file = self.file(id);
+        diagnostic.rebase(id, &file.index, file.base)
+    }
+
+    /// Render `diagnostic` against its file (see [`Diagnostic::render`]).
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let file = self.file(diagnostic.file);
+        diagnostic.render(&file.text, &file.path)
+    }
+}
+
+/// A document assembled from a root file and everything it includes.
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    /// Spans point into [`SourceMap::text`] of `sources`.
+    pub document: Document,
+    pub sources: SourceMap,
+    /// Parse and include errors, each located in its file.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Bundle {
+    /// Evaluate variables across all files and validate the document. The
+    /// diagnostics are located in their files.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        self.validate_with(&ValidateOptions::default())
+    }
+
+    /// [`Bundle::validate`] with the given validation options.
+    pub fn validate_with(&self, options: &ValidateOptions) -> Vec<Diagnostic> {
+        let text = self.sources.text();
+        let (document, mut diagnostics) = evaluate(text, &self.document);
+        diagnostics.extend(validate_document_with(text, &document, options));
+        diagnostics
+            .into_iter()
+            .map(|d| self.sources.localize(d))
+            .collect()
+    }
+}
+
+/// Parse `src`, the text of the file at `path`, replacing each `@include`
+/// line with the lines of the file it names.
+///
+/// Missing files, include cycles and nesting deeper than
+/// [`MAX_INCLUDE_DEPTH`] are reported at the `path` value; the offending
+/// `@include` is skipped.
+pub fn parse_with_includes(path: &str, src: &str, provider: &dyn FileProvider) -> Bundle {
+    let mut expander = Expander {
+        provider,
+        parser: LineParser::new(),
+        sources: SourceMap::default(),
+        assembler: Assembler::new(Section::Preamble),
+        diagnostics: Vec::new(),
+    };
+    let root = expander.sources.add(path.to_string(), src.to_string());
+    expander.expand(root, &mut vec![path.to_string()]);
+    let Expander {
+        sources,
+        assembler,
+        mut diagnostics,
+        ..
+    } = expander;
+    diagnostics.extend(assembler.diagnostics);
+    diagnostics.sort_by_key(|d| d.span.start);
+    Bundle {
+        document: assembler.doc,
+        diagnostics: diagnostics
+            .into_iter()
+            .map(|d| sources.localize(d))
+            .collect(),
+        sources,
+    }
+}
+
+struct Expander<'p> {
+    provider: &'p dyn FileProvider,
+    parser: LineParser,
+    sources: SourceMap,
+    assembler: Assembler,
+    /// Include errors, against the combined text.
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Expander<'_> {
+    /// Feed the lines of file `id` to the assembler. `stack` holds the paths
+    /// of the files being expanded, outermost first.
+    fn expand(&mut self, id: FileId, stack: &mut Vec<String>) {
+        let file = self.sources.file(id);
+        let (base, text) = (file.base, file.text.clone());
+        for (line_no, (offset, line)) in lines_with_offsets(&text).enumerate() {
+            let parse = self.parser.parse(line);
+            match &parse {
+                LineParse::Directive(directive) if directive.name == INCLUDE => {
+                    self.include(line, directive, base + offset, line_no, stack);
+                }
+                _ => self.assembler.push(line_no, base + offset, line, &parse),
+            }
+        }
+    }
+
+    /// Expand `directive`, parsed from `line`, which is line `line_no` of
+    /// its file and starts at combined offset `at`.
+    fn include(
+        &mut self,
+        line: &str,
+        directive: &Directive,
+        at: usize,
+        line_no: usize,
+        stack: &mut Vec<String>,
+    ) {
+        // Diagnostics are computed against the line, then moved into place.
+        let errors = validate_directive(line, directive);
+        if !errors.is_empty() {
+            self.diagnostics
+                .extend(errors.into_iter().map(|d| d.relocate(at, line_no + 1)));
+            return;
+        }
+        let directive = bind_args(directive);
+        let Some((path, span)) = directive.get("path").and_then(|p| match &p.value {
+            Value::String(path) => Some((path, p.value_span.clone())),
+            _ => None,
+        }) else {
+            return;
+        };
+        let from = stack.last().expect("the including file is on the stack");
+        let resolved = self.provider.resolve(from, path);
+        let error = |code, message: String| {
+            Diagnostic::error(line, code, span, message).relocate(at, line_no + 1)
+        };
+        if let Some(at) = stack.iter().position(|p| *p == resolved) {
+            let cycle: Vec<&str> = stack[at..]
+                .iter()
+                .map(String::as_str)
+                .chain([resolved.as_str()])
+                .collect();
+            let diagnostic = error(
+                codes::INCLUDE_CYCLE,
+                format!("`{resolved}` includes itself"),
+            )
+            .with_help(format!("cycle: {}", cycle.join(" -> ")));
+            self.diagnostics.push(diagnostic);
+        } else if stack.len() > MAX_INCLUDE_DEPTH {
+            let diagnostic = error(
+                codes::INCLUDE_DEPTH,
+                format!("includes nest more than {MAX_INCLUDE_DEPTH} levels deep"),
+            )
+            .with_help(format!("included from `{from}`"));
+            self.diagnostics.push(diagnostic);
+        } else {
+            match self.provider.read(&resolved) {
+                Ok(text) => {
+                    let id = self.sources.add(resolved.clone(), text);
+                    stack.push(resolved);
+                    self.expand(id, stack);
+                    stack.pop();
+                }
+                Err(err) => {
+                    let diagnostic = error(
+                        codes::INCLUDE_NOT_FOUND,
+                        format!("cannot read `{resolved}`: {err}"),
+                    );
+                    self.diagnostics.push(diagnostic);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::BodyKind;
+
+    fn provider(files: &[(&str, &str)]) -> MemoryProvider {
+        let mut provider = MemoryProvider::new();
+        for (path, text) in files {
+            provider.insert(*path, *text);
+        }
+        provider
+    }
+
+    #[test]
+    fn splices_included_lines() {
+        let files = provider(&[
+            ("bank/defs.q", "@define course=CS101\n@meta tags=[x]"),
+            (
+                "bank/answers/a.q",
+                "* Tokyo\n@include path=\"../../shared.q\"\n",
+            ),
+            ("shared.q", "- Kyoto\n"),
+        ]);
+        let root =
+            "@option\n@include path=defs.q\n${course}: ibu kota?\n@include path='answers/a.q'\n";
+        let bundle = parse_with_includes("bank/q1.q", root, &files);
+        assert_eq!(bundle.diagnostics, []);
+        assert_eq!(bundle.validate(), []);
+        let names: Vec<_> = bundle.document.headers.iter().map(|d| &d.name).collect();
+        assert_eq!(names, ["option", "define", "meta"]);
+        let body: Vec<_> = bundle
+            .document
+            .body
+            .iter()
+            .map(|node| {
+                let (id, span) = bundle.sources.locate(&node.span);
+                let file = bundle.sources.file(id);
+                (file.path.as_str(), &file.text[span])
+            })
+            .collect();
+        assert_eq!(
+            body,
+            [
+                ("bank/q1.q", "${course}: ibu kota?"),
+                ("bank/answers/a.q", "* Tokyo"),
+                ("shared.q", "- Kyoto"),
+            ]
+        );
+        assert!(matches!(&bundle.document.body[2].kind, BodyKind::Text(t) if t == "- Kyoto"));
+
+        // A fragment may open the bank, above the question directive.
+        let files = provider(&[
+            (
+                "bank/vars.q",
+                "@define course=CS101\n@include path=more.q\n",
+            ),
+            ("bank/more.q", "@define n=2\n"),
+        ]);
+        let root = "@include path=vars.q\n@option points=${n}\n${course}?\n* a\n- b\n";
+        let bundle = parse_with_includes("bank/q1.q", root, &files);
+        assert_eq!(bundle.diagnostics, []);
+        assert_eq!(bundle.validate(), []);
+        let names: Vec<_> = bundle.document.headers.iter().map(|d| &d.name).collect();
+        assert_eq!(names, ["define", "define", "option"]);
+    }
+
+    #[test]
+    fn reports_errors_in_their_file() {
+        let files = provider(&[
+            ("a.q", "@include path=b.q\n"),
+            (
+                "b.q",
+                "\n@x y=\n@include path=a.q\n@include path=gone.q\n@include\n",
+            ),
+        ]);
+        let bundle = parse_with_includes("a.q", "@option\n@include path=b.q\n", &files);
+        let summary: Vec<_> = bundle
+            .diagnostics
+            .iter()
+            .map(|d| {
+                let file = bundle.sources.file(d.file);
+                (
+                    file.path.as_str(),
+                    d.code,
+                    d.line,
+                    &file.text[d.span.clone()],
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("b.q", codes::MISSING_VALUE, 2, "y="),
+                ("b.q", codes::INCLUDE_CYCLE, 3, "a.q"),
+                ("b.q", codes::INCLUDE_NOT_FOUND, 4, "gone.q"),
+                ("b.q", codes::MISSING_KEY, 5, "@include"),
+            ]
+        );
+        assert_eq!(
+            bundle.diagnostics[1].help.as_deref(),
+            Some("cycle: a.q -> b.q -> a.q")
+        );
+        assert!(
+            bundle
+                .sources
+                .render(&bundle.diagnostics[2])
+                .contains(" --> b.q:4:15\n")
+        );
+
+        // A chain of distinct files still ends at the depth cap.
+        let mut chain = MemoryProvider::new();
+        for i in 0..=MAX_INCLUDE_DEPTH + 1 {
+            chain.insert(format!("{i}.q"), format!("@include path={}.q\n", i + 1));
+        }
+        let bundle = parse_with_includes("0.q", "@include path=1.q\n", &chain);
+        assert_eq!(bundle.diagnostics.len(), 1);
+        assert_eq!(bundle.diagnostics[0].code, codes::INCLUDE_DEPTH);
+        let file = bundle.sources.file(bundle.diagnostics[0].file);
+        assert_eq!(file.path, format!("{MAX_INCLUDE_DEPTH}.q"));
+    }
+}
@@ -0,0 +1,416 @@
+//! Incremental reparsing.
+//!
+//! The grammar is line based: a line's parse never depends on its neighbours,
+//! only its position (header or body) does. [`IncrementalParser`] therefore
+//! keeps one cached [`LineParse`] per line, reparses only the lines an edit
+//! touches, and splices their nodes into the [`Document`]; nodes after the
+//! edit only have their spans shifted.
+//!
+//! The text and the line offsets are updated in place too, so an edit costs
+//! the size of the touched lines plus a shift of the later offsets, not a
+//! rescan of the whole document.
+
+use std::rc::Rc;
+
+use crate::diagnostic::Diagnostic;
+use crate::parser::{Assembler, Document, LineParse, LineParser, Section, Span};
+
+/// Replace the bytes in `range` (offsets into the text as it is when this
+/// edit is applied) with `text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub range: Span,
+    pub text: String,
+}
+
+impl TextEdit {
+    pub fn new(range: Span, text: impl Into<String>) -> Self {
+        TextEdit {
+            range,
+            text: text.into(),
+        }
+    }
+}
+
+/// What an [`IncrementalParser::apply`] call reparsed.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Changes {
+    /// Indices of the lines that were reparsed, in the new text.
+    pub lines: Vec<usize>,
+    /// Whether the header directive is different from before.
+    pub header: bool,
+    /// Indices of reparsed nodes in [`Document::body`].
+    pub body: Vec<usize>,
+}
+
+struct Line {
+    /// Length of the raw line including its terminator.
+    len: usize,
+    parse: Rc<LineParse>,
+    fresh: bool,
+}
+
+/// A raw line without its `\n` or `\r\n` terminator.
+fn content(raw: &str) -> &str {
+    let content = raw.strip_suffix('\n').unwrap_or(raw);
+    content.strip_suffix('\r').unwrap_or(content)
+}
+
+pub struct IncrementalParser {
+    parser: LineParser,
+    text: String,
+    lines: Vec<Line>,
+    /// Byte offset of each line's start.
+    starts: Vec<usize>,
+    document: Document,
+    diagnostics: Vec<Diagnostic>,
+    /// Per line, the index of its node in `document.body`.
+    body_index: Vec<Option<usize>>,
+}
+
+impl IncrementalParser {
+    pub fn new(src: &str) -> Self {
+        let mut this = IncrementalParser {
+            parser: LineParser::new(),
+            text: String::new(),
+            lines: Vec::new(),
+            starts: Vec::new(),
+            document: Document::default(),
+            diagnostics: Vec::new(),
+            body_index: Vec::new(),
+        };
+        this.apply(&[TextEdit::new(0..0, src)]);
+        this
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The byte offset and content, without terminator, of line `index`.
+    pub fn line(&self, index: usize) -> Option<(usize, &str)> {
+        let start = *self.starts.get(index)?;
+        Some((start, content(self.raw(index))))
+    }
+
+    fn raw(&self, index: usize) -> &str {
+        let start = self.starts[index];
+        &self.text[start..start + self.lines[index].len]
+    }
+
+    pub fn document(&self) -> &Document {
+        &self.document
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Apply `edits` in order and reparse the touched lines.
+    ///
+    /// Panics if an edit range is out of bounds or not on a char boundary.
+    pub fn apply(&mut self, edits: &[TextEdit]) -> Changes {
+        for line in &mut self.lines {
+            line.fresh = false;
+        }
+        let old_header = self.header_parses();
+        for edit in edits {
+            self.apply_one(edit);
+        }
+        let mut changes = Changes {
+            // Line-relative parses compare equal when only the position moved.
+            header: old_header != self.header_parses(),
+            ..Changes::default()
+        };
+        for (i, line) in self.lines.iter().enumerate() {
+            if line.fresh {
+                changes.lines.push(i);
+                changes.body.extend(self.body_index[i]);
+            }
+        }
+        changes
+    }
+
+    /// The cached parses of the header block's directive lines.
+    fn header_parses(&self) -> Vec<Rc<LineParse>> {
+        let preamble = self.preamble_len();
+        self.lines[preamble..self.body_start()]
+            .iter()
+            .filter(|l| !matches!(*l.parse, LineParse::Comment))
+            .map(|l| l.parse.clone())
+            .collect()
+    }
+
+    /// Number of blank and comment lines before the header block.
+    fn preamble_len(&self) -> usize {
+        self.lines
+            .iter()
+            .take_while(|l| matches!(*l.parse, LineParse::Blank | LineParse::Comment))
+            .count()
+    }
+
+    /// Index of the first body line, or the line count if there is none.
+    fn body_start(&self) -> usize {
+        let preamble = self.preamble_len();
+        preamble
+            + self.lines[preamble..]
+                .iter()
+                .take_while(|l| {
+                    matches!(
+                        *l.parse,
+                        LineParse::Directive(_) | LineParse::Invalid(..) | LineParse::Comment
+                    )
+                })
+                .count()
+    }
+
+    fn line_at(&self, offset: usize) -> usize {
+        self.starts
+            .partition_point(|&start| start <= offset)
+            .saturating_sub(1)
+    }
+
+    fn apply_one(&mut self, edit: &TextEdit) {
+        let TextEdit { range, text } = edit;
+        assert!(
+            range.start <= range.end && range.end <= self.text.len(),
+            "edit {range:?} out of bounds for text of length {}",
+            self.text.len()
+        );
+        let first = self.line_at(range.start);
+        let mut last = self.line_at(range.end).max(first);
+        let base = self.starts.get(first).copied().unwrap_or(0);
+        let old_end = match self.lines.get(last) {
+            Some(line) => self.starts[last] + line.len,
+            None => base,
+        };
+
+        self.text.replace_range(range.clone(), text);
+        let delta = text.len() as isize - range.len() as isize;
+        let mut chunk_end = old_end.saturating_add_signed(delta);
+        // A line that lost its terminator merges with the next one.
+        while !self.text[base..chunk_end].ends_with('\n') && last + 1 < self.lines.len() {
+            last += 1;
+            chunk_end += self.lines[last].len;
+        }
+        let end = (last + 1).min(self.lines.len());
+
+        let mut new_starts = Vec::new();
+        let mut offset = base;
+        let new_lines: Vec<Line> = self.text[base..chunk_end]
+            .split_inclusive('\n')
+            .map(|raw| {
+                new_starts.push(offset);
+                offset += raw.len();
+                Line {
+                    len: raw.len(),
+                    parse: Rc::new(self.parser.parse(content(raw))),
+                    fresh: true,
+                }
+            })
+            .collect();
+        let added = new_lines.len();
+        let body_start = self.body_start();
+        let old_count = self.lines.len();
+        self.lines.splice(first..end, new_lines);
+        self.starts.splice(first..end, new_starts);
+        for start in &mut self.starts[first + added..] {
+            *start = start.saturating_add_signed(delta);
+        }
+
+        // Lines before the edit keep their nodes. An edit in the header block,
+        // the blank lines before it or the line that ends it can move the
+        // header/body boundary, so everything up to that line is rebuilt.
+        let (from, old_to, section) = if first > body_start {
+            (first, end, Section::Body)
+        } else {
+            (0, end.max(body_start + 1).min(old_count), Section::Preamble)
+        };
+        let new_to = old_to + added - (end - first);
+        self.reassemble(from, old_to, new_to, delta, section);
+    }
+
+    /// Rebuild the nodes of new lines `from..new_to`, which replaced old lines
+    /// `from..old_to`; later lines are shifted by `delta` bytes. `section` is
+    /// the section line `from` starts in.
+    fn reassemble(
+        &mut self,
+        from: usize,
+        mut old_to: usize,
+        mut new_to: usize,
+        delta: isize,
+        section: Section,
+    ) {
+        let mut assembler = Assembler::new(section);
+        for i in from..new_to {
+            assembler.push(
+                i,
+                self.starts[i],
+                content(self.raw(i)),
+                &self.lines[i].parse,
+            );
+        }
+        // Old lines after `old_to` were body lines; until the rebuilt lines
+        // reach the body, the following lines need rebuilding too.
+        while assembler.section != Section::Body && new_to < self.lines.len() {
+            let raw = content(self.raw(new_to));
+            assembler.push(new_to, self.starts[new_to], raw, &self.lines[new_to].parse);
+            new_to += 1;
+            old_to += 1;
+        }
+
+        let first_node_from = |index: &[Option<usize>], at: usize, len: usize| {
+            index[at.min(index.len())..]
+                .iter()
+                .flatten()
+                .next()
+                .copied()
+                .unwrap_or(len)
+        };
+        let body_len = self.document.body.len();
+        let body_from = first_node_from(&self.body_index, from, body_len);
+        let body_to = first_node_from(&self.body_index, old_to, body_len);
+        let added_nodes = assembler.doc.body.len();
+        self.document
+            .body
+            .splice(body_from..body_to, assembler.doc.body);
+        for node in &mut self.document.body[body_from + added_nodes..] {
+            node.shift(delta);
+        }
+        if section != Section::Body {
+            self.document.headers = assembler.doc.headers;
+        }
+
+        let node_shift = (body_from + added_nodes) as isize - body_to as isize;
+        let tail: Vec<Option<usize>> = self.body_index[old_to.min(self.body_index.len())..]
+            .iter()
+            .map(|i| i.map(|i| i.saturating_add_signed(node_shift)))
+            .collect();
+        self.body_index.truncate(from);
+        self.body_index.extend(
+            assembler
+                .body_index
+                .into_iter()
+                .map(|i| i.map(|i| i + body_from)),
+        );
+        self.body_index.extend(tail);
+
+        // Diagnostics are ordered by line (1-based).
+        let diag_from = self.diagnostics.partition_point(|d| d.line <= from);
+        let diag_to = self.diagnostics.partition_point(|d| d.line <= old_to);
+        let added_diags = assembler.diagnostics.len();
+        self.diagnostics
+            .splice(diag_from..diag_to, assembler.diagnostics);
+        let line_shift = new_to as isize - old_to as isize;
+        for diagnostic in &mut self.diagnostics[diag_from + added_diags..] {
+            let span = &diagnostic.span;
+            diagnostic.span =
+                span.start.saturating_add_signed(delta)..span.end.saturating_add_signed(delta);
+            diagnostic.line = diagnostic.line.saturating_add_signed(line_shift);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_document_recovering;
+
+    const SAMPLE: &str =
+        "\n@option count=2 tags=[a, b]\nWhat is 2+2?\r\n\n@meta x=\"y\"\n- 4\n@bad key=\nend";
+
+    fn assert_matches_scratch(parser: &IncrementalParser) {
+        let text = parser.text();
+        let (document, diagnostics) = parse_document_recovering(text);
+        assert_eq!(
+            parser.document(),
+            &document,
+            "document differs for {text:?}"
+        );
+        assert_eq!(
+            parser.diagnostics(),
+            diagnostics,
+            "diagnostics differ for {text:?}"
+        );
+    }
+
+    #[test]
+    fn reports_changed_nodes() {
+        let mut parser = IncrementalParser::new(SAMPLE);
+        assert_matches_scratch(&parser);
+        let at = SAMPLE.find("What").unwrap();
+        let changes = parser.apply(&[TextEdit::new(at..at + 4, "Why")]);
+        assert_eq!(changes.lines, [2]);
+        assert!(!changes.header);
+        assert_eq!(changes.body, [0]);
+        let changes = parser.apply(&[TextEdit::new(0..1, "")]);
+        assert_eq!(changes.lines, [0]);
+        assert!(!changes.header);
+        let changes = parser.apply(&[TextEdit::new(14..15, "3")]);
+        assert!(changes.header);
+        assert_matches_scratch(&parser);
+    }
+
+    #[test]
+    fn edits_in_large_banks_touch_only_their_lines() {
+        let mut text = String::from("@option points=1\n");
+        for i in 0..50_000 {
+            text.push_str(&format!("Line {i} of the question?\n"));
+        }
+        let mut parser = IncrementalParser::new(&text);
+        for i in (1..50_000).step_by(997) {
+            let (start, line) = parser.line(i).unwrap();
+            assert_eq!(line, format!("Line {} of the question?", i - 1));
+            let at = start + line.len();
+            let changes = parser.apply(&[TextEdit::new(at..at, " ${x}")]);
+            text.replace_range(at..at, " ${x}");
+            assert_eq!(changes.lines, [i]);
+            assert!(!changes.header);
+        }
+        assert_eq!(parser.text(), text);
+        assert_matches_scratch(&parser);
+    }
+
+    #[test]
+    fn random_edits_equal_scratch_parse() {
+        const PIECES: &[&str] = &[
+            "",
+            "\n",
+            "@",
+            "=",
+            " ",
+            "x",
+            "\r\n",
+            "@option a=1\n",
+            "@meta b=2\n",
+            "# c",
+            " //",
+            "[",
+            "\"",
+            "é",
+        ];
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut next = |bound: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % bound as u64) as usize
+        };
+        let mut parser = IncrementalParser::new(SAMPLE);
+        let mut text = SAMPLE.to_string();
+        for _ in 0..500 {
+            let boundaries: Vec<usize> = (0..=text.len())
+                .filter(|&i| text.is_char_boundary(i))
+                .collect();
+            let a = boundaries[next(boundaries.len())];
+            let b = boundaries[next(boundaries.len())];
+            let range = a.min(b)..a.max(b).min(a.min(b) + 8);
+            let range = range.start..*boundaries.iter().rfind(|&&i| i <= range.end).unwrap();
+            let insert = PIECES[next(PIECES.len())];
+            text.replace_range(range.clone(), insert);
+            parser.apply(&[TextEdit::new(range, insert)]);
+            assert_eq!(parser.text(), text);
+            assert_matches_scratch(&parser);
+        }
+    }
+}
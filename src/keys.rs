@@ -1,4 +1,5 @@
-use crate::layout::renumber_lines;
+use crate::format::format;
+use crate::layout::{document_text, renumber_lines, set_document_text};
 use crate::line_handlers::{create_line, get_headers};
 use crate::log;
 use crate::header_auto_complete::{cycle_overlay, accept_overlay_selection, overlay_active};
@@ -18,6 +19,11 @@ pub fn attach_key_handler(container: &web_sys::HtmlElement) {
             handle_alt_x(&container_clone);
             return;
         }
+        if event.alt_key() && event.shift_key() && (key == "f" || key == "F") {
+            event.prevent_default();
+            handle_format(&container_clone);
+            return;
+        }
         match key.as_str() {
             "Enter" => handle_enter(&event),
             "Backspace" => handle_backspace(&event, &container_clone),
@@ -42,6 +48,16 @@ fn handle_alt_x(container: &HtmlElement) {
     }
 }
 
+/// "Format document": rewrite every line with the canonical formatter.
+fn handle_format(container: &HtmlElement) {
+    let Some(doc) = container.owner_document() else {
+        return;
+    };
+    let formatted = format(&document_text(container));
+    set_document_text(container, &formatted, |i| create_line(&doc, i));
+    log::tagged("FORMAT", "Document formatted");
+}
+
 fn handle_enter(event: &web_sys::KeyboardEvent) {
     event.prevent_default();
     if let Some(target) = event.target() {
@@ -69,3 +69,63 @@ pub(crate) fn init(
         }
     }
 }
+
+/// Text of every line's code span, joined with `\n`.
+pub(crate) fn document_text(container: &web_sys::HtmlElement) -> String {
+    let mut lines = Vec::new();
+    let mut maybe_child = container.first_element_child();
+    while let Some(child) = maybe_child {
+        if child.class_name() == "wasm-line"
+            && let Some(code) = child.last_element_child()
+        {
+            lines.push(code.text_content().unwrap_or_default());
+        }
+        maybe_child = child.next_element_sibling();
+    }
+    lines.join("\n")
+}
+
+/// Replace the editor content with `text`, one line element per line,
+/// creating or removing line elements as needed.
+pub(crate) fn set_document_text(
+    container: &web_sys::HtmlElement,
+    text: &str,
+    create_line: impl Fn(usize) -> Option<web_sys::Element>,
+) {
+    let mut lines = text.lines();
+    let mut maybe_child = container.first_element_child();
+    let mut index = 1usize;
+    while let Some(child) = maybe_child {
+        maybe_child = child.next_element_sibling();
+        if child.class_name() != "wasm-line" {
+            continue;
+        }
+        match lines.next() {
+            Some(line) => {
+                if let Some(code) = child.last_element_child() {
+                    code.set_text_content(Some(line));
+                }
+            }
+            // Keep the header line even when the document is empty.
+            None if index == 1 => {
+                if let Some(code) = child.last_element_child() {
+                    code.set_text_content(Some(""));
+                }
+            }
+            None => {
+                let _ = container.remove_child(&child);
+            }
+        }
+        index += 1;
+    }
+    for line in lines {
+        if let Some(el) = create_line(index) {
+            if let Some(code) = el.last_element_child() {
+                code.set_text_content(Some(line));
+            }
+            let _ = container.append_child(&el);
+        }
+        index += 1;
+    }
+    renumber_lines(container);
+}
@@ -1,13 +1,33 @@
 use wasm_bindgen::JsCast;
 use wasm_bindgen::prelude::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cli;
+pub mod de;
+pub mod diagnostic;
+pub mod eval;
+pub mod format;
 pub mod header_auto_complete;
+pub mod highlight;
 pub mod import;
+pub mod include;
+pub mod incremental;
 pub mod keys;
 pub mod layout; // new module for layout & line population
 pub mod line_handlers;
+pub mod line_index;
 pub mod log;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod lsp;
 pub mod parser;
+pub mod question;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod repl;
+pub mod schema;
+pub mod semantic;
+pub mod ser;
 pub mod style; // include parser module for native tests
+pub mod syntax;
+pub mod value;
 pub use line_handlers::create_line;
 
 use crate::layout::{init, inject_base_styles};
@@ -57,5 +77,29 @@ pub fn init_editor() {
     }
 }
 
+/// Format DSL source with the default options (see [`format::format`]).
+#[wasm_bindgen]
+pub fn format_document(src: &str) -> String {
+    format::format(src)
+}
+
+/// Semantic tokens of the whole document as JSON (see [`semantic::semantic_tokens`]).
+#[wasm_bindgen]
+pub fn semantic_tokens_json(src: &str) -> String {
+    semantic::semantic_tokens_json(src)
+}
+
+/// Semantic tokens in the LSP delta encoding (see [`semantic::encode_delta`]).
+#[wasm_bindgen]
+pub fn semantic_tokens_delta(src: &str) -> Vec<u32> {
+    semantic::encode_delta(&semantic::semantic_tokens(src))
+}
+
+/// The token type and modifier names the delta encoding indexes into, as JSON.
+#[wasm_bindgen]
+pub fn semantic_tokens_legend() -> String {
+    serde_json::to_string(&semantic::LEGEND).expect("legend serializes to JSON")
+}
+
 #[wasm_bindgen]
 pub fn noop() {}
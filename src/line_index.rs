@@ -0,0 +1,286 @@
+//! Position mapping between byte, char and UTF-16 offsets.
+//!
+//! Spans throughout the crate are byte offsets into the source. The browser
+//! (DOM `Selection`, JS strings) and LSP clients count UTF-16 code units, and
+//! diagnostics show character columns; [`LineIndex`] converts between them.
+
+use serde::Serialize;
+
+/// The unit offsets and columns are counted in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// Bytes; the crate's native unit.
+    #[default]
+    Utf8,
+    /// UTF-16 code units, as JavaScript and LSP count.
+    Utf16,
+    /// Unicode scalar values.
+    Char,
+}
+
+/// A 0-based line and column. The column unit depends on the [`Encoding`]
+/// the position was computed for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize)]
+pub struct LineCol {
+    pub line: u32,
+    pub col: u32,
+}
+
+/// A character that is more than one byte long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct WideChar {
+    /// Byte column of the character's start.
+    start: usize,
+    len_utf8: usize,
+    len_utf16: usize,
+}
+
+impl WideChar {
+    fn len(&self, encoding: Encoding) -> usize {
+        match encoding {
+            Encoding::Utf8 => self.len_utf8,
+            Encoding::Utf16 => self.len_utf16,
+            Encoding::Char => 1,
+        }
+    }
+}
+
+/// Line starts and multi-byte characters of a text, for offset conversion.
+///
+/// Lines end at `\n`; a preceding `\r` counts as part of the line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineIndex {
+    len: usize,
+    /// Byte offset of each line's start.
+    starts: Vec<usize>,
+    /// Char and UTF-16 offset of each line's start.
+    char_starts: Vec<usize>,
+    utf16_starts: Vec<usize>,
+    /// Multi-byte characters of each line, in order.
+    wide: Vec<Vec<WideChar>>,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let mut index = LineIndex {
+            len: text.len(),
+            starts: vec![0],
+            char_starts: vec![0],
+            utf16_starts: vec![0],
+            wide: vec![Vec::new()],
+        };
+        let (mut chars, mut utf16) = (0, 0);
+        for (offset, c) in text.char_indices() {
+            chars += 1;
+            utf16 += c.len_utf16();
+            if c == '\n' {
+                index.starts.push(offset + 1);
+                index.char_starts.push(chars);
+                index.utf16_starts.push(utf16);
+                index.wide.push(Vec::new());
+            } else if !c.is_ascii() {
+                let line_start = index.starts[index.starts.len() - 1];
+                index.wide.last_mut().unwrap().push(WideChar {
+                    start: offset - line_start,
+                    len_utf8: c.len_utf8(),
+                    len_utf16: c.len_utf16(),
+                });
+            }
+        }
+        index
+    }
+
+    /// Length of the text in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.starts.len()
+    }
+
+    /// Byte offset where `line` starts.
+    pub fn line_start(&self, line: u32) -> Option<usize> {
+        self.starts.get(line as usize).copied()
+    }
+
+    /// Position of byte `offset`, with the column counted in `encoding`.
+    /// Offsets past the end or inside a character are clamped back.
+    pub fn line_col(&self, offset: usize, encoding: Encoding) -> LineCol {
+        let offset = offset.min(self.len);
+        let line = self.starts.partition_point(|&s| s <= offset) - 1;
+        let byte_col = offset - self.starts[line];
+        let mut col = byte_col;
+        for w in self.wide[line].iter().take_while(|w| w.start < byte_col) {
+            if byte_col < w.start + w.len_utf8 {
+                // Inside the character: round down to its start.
+                col -= byte_col - w.start;
+                break;
+            }
+            col = col - w.len_utf8 + w.len(encoding);
+        }
+        LineCol {
+            line: line as u32,
+            col: col as u32,
+        }
+    }
+
+    /// Byte offset of `pos`, whose column is counted in `encoding`. Columns past
+    /// the end of the line are clamped to it; `None` if the line does not exist
+    /// or the column falls inside a character.
+    pub fn offset(&self, pos: LineCol, encoding: Encoding) -> Option<usize> {
+        let line = pos.line as usize;
+        let start = *self.starts.get(line)?;
+        let end = self.starts.get(line + 1).map_or(self.len, |next| next - 1);
+        let col = pos.col as usize;
+        // Bytes the characters before `col` take beyond their length in `encoding`.
+        let mut extra = 0;
+        for w in &self.wide[line] {
+            let w_start = w.start - extra;
+            if w_start >= col {
+                break;
+            }
+            if col < w_start + w.len(encoding) {
+                return None;
+            }
+            extra += w.len_utf8 - w.len(encoding);
+        }
+        Some((start + col + extra).min(end))
+    }
+
+    /// Convert a whole-text offset between encodings. `None` if `offset` is
+    /// past the end or inside a character.
+    pub fn convert(&self, offset: usize, from: Encoding, to: Encoding) -> Option<usize> {
+        let starts = self.starts_in(from);
+        let line = starts.partition_point(|&s| s <= offset) - 1;
+        let col = offset - starts[line];
+        let pos = LineCol {
+            line: line as u32,
+            col: col as u32,
+        };
+        let byte = self.offset(pos, from)?;
+        if self.line_col(byte, from) != pos {
+            // Clamped: the offset is past the end of its line or the text.
+            return None;
+        }
+        Some(self.starts_in(to)[line] + self.line_col(byte, to).col as usize)
+    }
+
+    /// Convert a byte span to `encoding`.
+    pub fn span(&self, span: std::ops::Range<usize>, encoding: Encoding) -> std::ops::Range<usize> {
+        let convert = |offset| {
+            let pos = self.line_col(offset, encoding);
+            self.starts_in(encoding)[pos.line as usize] + pos.col as usize
+        };
+        convert(span.start)..convert(span.end)
+    }
+
+    fn starts_in(&self, encoding: Encoding) -> &[usize] {
+        match encoding {
+            Encoding::Utf8 => &self.starts,
+            Encoding::Utf16 => &self.utf16_starts,
+            Encoding::Char => &self.char_starts,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "Apa ibu kota?\r\n日本語 😀x\n\né";
+
+    #[test]
+    fn line_col_in_every_encoding() {
+        let index = LineIndex::new(TEXT);
+        assert_eq!(index.line_count(), 4);
+        let x = TEXT.find('x').unwrap();
+        let at = |encoding| index.line_col(x, encoding);
+        assert_eq!(at(Encoding::Utf8), LineCol { line: 1, col: 14 });
+        assert_eq!(at(Encoding::Utf16), LineCol { line: 1, col: 6 });
+        assert_eq!(at(Encoding::Char), LineCol { line: 1, col: 5 });
+        for encoding in [Encoding::Utf8, Encoding::Utf16, Encoding::Char] {
+            assert_eq!(index.offset(at(encoding), encoding), Some(x));
+        }
+        // Inside the emoji's surrogate pair, and past the end of a line.
+        assert_eq!(
+            index.offset(LineCol { line: 1, col: 5 }, Encoding::Utf16),
+            None
+        );
+        assert_eq!(
+            index.offset(LineCol { line: 2, col: 9 }, Encoding::Char),
+            Some(TEXT.len() - 3)
+        );
+        assert_eq!(
+            index.offset(LineCol { line: 4, col: 0 }, Encoding::Utf8),
+            None
+        );
+        assert_eq!(
+            index.line_col(x - 1, Encoding::Utf16),
+            LineCol { line: 1, col: 4 }
+        );
+        assert_eq!(
+            index.line_col(TEXT.len(), Encoding::Char),
+            LineCol { line: 3, col: 1 }
+        );
+    }
+
+    #[test]
+    fn whole_text_offsets_round_trip() {
+        let index = LineIndex::new(TEXT);
+        let bytes = TEXT.char_indices().map(|(i, _)| i).chain([TEXT.len()]);
+        for byte in bytes {
+            let u = index
+                .convert(byte, Encoding::Utf8, Encoding::Utf16)
+                .unwrap();
+            assert_eq!(u, TEXT[..byte].encode_utf16().count());
+            let ch = index.convert(byte, Encoding::Utf8, Encoding::Char).unwrap();
+            assert_eq!(ch, TEXT[..byte].chars().count());
+            assert_eq!(
+                index.convert(u, Encoding::Utf16, Encoding::Utf8),
+                Some(byte)
+            );
+            assert_eq!(index.convert(ch, Encoding::Char, Encoding::Utf16), Some(u));
+        }
+        assert_eq!(
+            index.convert(TEXT.len() + 1, Encoding::Utf8, Encoding::Char),
+            None
+        );
+        assert_eq!(index.span(15..24, Encoding::Utf16), 15..18);
+    }
+
+    #[test]
+    fn end_of_text_with_and_without_a_final_newline() {
+        use crate::diagnostic::Locate;
+
+        for (text, end) in [
+            ("", (1, 1)),
+            ("é", (1, 2)),
+            ("ab\ncd", (2, 3)),
+            ("ab\ncd\n", (3, 1)),
+            ("ab\r\n", (2, 1)),
+        ] {
+            let index = LineIndex::new(text);
+            assert_eq!(index.line_column(text.len()), end, "{text:?}");
+            for encoding in [Encoding::Utf8, Encoding::Utf16, Encoding::Char] {
+                let pos = index.line_col(text.len(), encoding);
+                assert_eq!(index.offset(pos, encoding), Some(text.len()), "{text:?}");
+            }
+        }
+        let index = LineIndex::new("ab\ncd");
+        assert_eq!(index.line_count(), 2);
+        // Past the end of the last line clamps to the end of the text.
+        assert_eq!(
+            index.offset(LineCol { line: 1, col: 3 }, Encoding::Utf8),
+            Some(5)
+        );
+        assert_eq!(
+            index.offset(LineCol { line: 2, col: 0 }, Encoding::Utf8),
+            None
+        );
+    }
+}
@@ -0,0 +1,867 @@
+//! Language server.
+//!
+//! [`serve`] speaks the Language Server Protocol over a [`Connection`]; the
+//! `lsp` subcommand runs it on standard input and output (see
+//! [`serve_stdio`]) and tests drive it in-process over
+//! [`Connection::memory`]. Each open document is kept in an
+//! [`IncrementalParser`] and synced incrementally. After every change the
+//! server publishes the parse, variable and schema diagnostics, expanding
+//! the `@include`s of documents on disk; it also answers completion of
+//! directive names, keys and values, hover, semantic tokens, formatting and
+//! document symbols. Positions are counted in UTF-16, the protocol's default.
+
+use std::collections::HashMap;
+use std::error::Error;
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as LspNotification, PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, DocumentSymbolRequest, Formatting, HoverRequest, Request as LspRequest,
+    SemanticTokensFullRequest,
+};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    DiagnosticRelatedInformation, DiagnosticSeverity, DidChangeTextDocumentParams,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, DocumentFormattingParams,
+    DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse, Documentation, Hover,
+    HoverContents, HoverParams, HoverProviderCapability, Location, MarkupContent, MarkupKind,
+    NumberOrString, OneOf, Position, PositionEncodingKind, PublishDiagnosticsParams, Range,
+    SemanticToken, SemanticTokenType, SemanticTokens, SemanticTokensFullOptions,
+    SemanticTokensLegend, SemanticTokensOptions, SemanticTokensParams, SemanticTokensResult,
+    SemanticTokensServerCapabilities, ServerCapabilities, SymbolKind, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextEdit as LspTextEdit, Uri,
+};
+use strum::VariantNames;
+
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::eval::{DEFINE, evaluate};
+use crate::format::format;
+use crate::import::Import;
+use crate::include::{FileId, FsProvider, INCLUDE, parse_with_includes};
+use crate::incremental::{IncrementalParser, TextEdit};
+use crate::line_index::{Encoding, LineCol, LineIndex};
+use crate::parser::{BodyKind, Directive, Document, Span};
+use crate::schema::{
+    DirectiveSchema, ParamSpec, ParamType, SCHEMAS, ValidateOptions, is_preamble, schema_for,
+    validate_document_with,
+};
+use crate::semantic::{LEGEND, encode_delta, semantic_tokens};
+use crate::value::Value;
+
+pub type ServeError = Box<dyn Error + Send + Sync>;
+
+/// Serve on standard input and output until the client exits, validating
+/// with `options`.
+pub fn serve_stdio(options: ValidateOptions) -> Result<(), ServeError> {
+    let (connection, io_threads) = Connection::stdio();
+    serve_with(&connection, options)?;
+    // The writer thread stops once the connection's sender is gone.
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+/// What the server supports, as sent in the `initialize` response.
+pub fn capabilities() -> ServerCapabilities {
+    let legend = SemanticTokensLegend {
+        token_types: LEGEND
+            .token_types
+            .iter()
+            .map(|t| SemanticTokenType::new(t))
+            .collect(),
+        token_modifiers: LEGEND
+            .token_modifiers
+            .iter()
+            .map(|m| lsp_types::SemanticTokenModifier::new(m))
+            .collect(),
+    };
+    ServerCapabilities {
+        position_encoding: Some(PositionEncodingKind::UTF16),
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(
+            TextDocumentSyncKind::INCREMENTAL,
+        )),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec!["@".into(), " ".into(), "=".into()]),
+            ..CompletionOptions::default()
+        }),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+            SemanticTokensOptions {
+                legend,
+                full: Some(SemanticTokensFullOptions::Bool(true)),
+                ..SemanticTokensOptions::default()
+            },
+        )),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    }
+}
+
+/// Run the initialize handshake on `connection`, then answer messages until
+/// the client asks to shut down.
+pub fn serve(connection: &Connection) -> Result<(), ServeError> {
+    serve_with(connection, ValidateOptions::default())
+}
+
+/// [`serve`], validating documents with `options`.
+pub fn serve_with(connection: &Connection, options: ValidateOptions) -> Result<(), ServeError> {
+    connection.initialize(serde_json::to_value(capabilities())?)?;
+    let mut server = Server {
+        documents: HashMap::new(),
+        options,
+    };
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+                connection.sender.send(server.handle(request).into())?;
+            }
+            Message::Notification(notification) => {
+                if let Some(uri) = server.notify(notification) {
+                    connection.sender.send(server.publish(uri).into())?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+struct Server {
+    documents: HashMap<Uri, IncrementalParser>,
+    options: ValidateOptions,
+}
+
+/// Decode the parameters of `request` and answer it with `handler`.
+fn call<R: LspRequest>(request: Request, handler: impl FnOnce(R::Params) -> R::Result) -> Response {
+    match serde_json::from_value(request.params) {
+        Ok(params) => Response::new_ok(request.id, handler(params)),
+        Err(e) => Response::new_err(request.id, ErrorCode::InvalidParams as i32, e.to_string()),
+    }
+}
+
+impl Server {
+    fn handle(&self, request: Request) -> Response {
+        match request.method.as_str() {
+            Completion::METHOD => call::<Completion>(request, |p| self.completion(p)),
+            HoverRequest::METHOD => call::<HoverRequest>(request, |p| self.hover(p)),
+            SemanticTokensFullRequest::METHOD => {
+                call::<SemanticTokensFullRequest>(request, |p| self.semantic_tokens(p))
+            }
+            Formatting::METHOD => call::<Formatting>(request, |p| self.formatting(p)),
+            DocumentSymbolRequest::METHOD => {
+                call::<DocumentSymbolRequest>(request, |p| self.symbols(p))
+            }
+            method => Response::new_err(
+                request.id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unsupported request `{method}`"),
+            ),
+        }
+    }
+
+    /// Apply a document notification. Returns the document whose diagnostics
+    /// need publishing.
+    fn notify(&mut self, notification: Notification) -> Option<Uri> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams =
+                    serde_json::from_value(notification.params).ok()?;
+                let document = params.text_document;
+                self.documents
+                    .insert(document.uri.clone(), IncrementalParser::new(&document.text));
+                Some(document.uri)
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: DidChangeTextDocumentParams =
+                    serde_json::from_value(notification.params).ok()?;
+                let uri = params.text_document.uri;
+                let parser = self.documents.get_mut(&uri)?;
+                for change in params.content_changes {
+                    match change.range {
+                        Some(range) => {
+                            let range =
+                                edit_offset(parser, range.start)..edit_offset(parser, range.end);
+                            parser.apply(&[TextEdit::new(range, change.text)]);
+                        }
+                        None => *parser = IncrementalParser::new(&change.text),
+                    }
+                }
+                Some(uri)
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams =
+                    serde_json::from_value(notification.params).ok()?;
+                self.documents.remove(&params.text_document.uri);
+                Some(params.text_document.uri)
+            }
+            _ => None,
+        }
+    }
+
+    /// The diagnostics of `uri`; none once the document is closed.
+    fn publish(&self, uri: Uri) -> Notification {
+        let diagnostics = match self.documents.get(&uri) {
+            Some(parser) => {
+                let text = parser.text();
+                let index = LineIndex::new(text);
+                check(&uri, text, parser, &self.options)
+                    .iter()
+                    .map(|d| to_lsp(&uri, &index, d))
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        Notification::new(
+            PublishDiagnostics::METHOD.to_string(),
+            PublishDiagnosticsParams {
+                uri,
+                diagnostics,
+                version: None,
+            },
+        )
+    }
+
+    fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let at = params.text_document_position;
+        let parser = self.documents.get(&at.text_document.uri)?;
+        let text = parser.text();
+        let offset = offset(&LineIndex::new(text), at.position);
+        let items = complete(text, parser.document(), offset);
+        Some(CompletionResponse::Array(items))
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let at = params.text_document_position_params;
+        let parser = self.documents.get(&at.text_document.uri)?;
+        let text = parser.text();
+        let index = LineIndex::new(text);
+        let offset = offset(&index, at.position);
+        let directive = directives(parser.document()).find(|d| contains(&d.span, offset))?;
+        let schema = schema_for(&directive.name)?;
+        let (markdown, span) = if contains(&directive.name_span, offset) {
+            (describe_directive(schema), directive.name_span.clone())
+        } else if let Some(pair) = directive
+            .pairs
+            .iter()
+            .find(|p| contains(&p.key_span, offset))
+        {
+            (
+                describe_param(schema.param(&pair.key)?),
+                pair.key_span.clone(),
+            )
+        } else {
+            let (i, arg) = directive
+                .args
+                .iter()
+                .enumerate()
+                .find(|(_, a)| contains(&a.span, offset))?;
+            let param = schema.param(schema.positional.get(i)?)?;
+            (describe_param(param), arg.span.clone())
+        };
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: markdown,
+            }),
+            range: Some(range(&index, &span)),
+        })
+    }
+
+    fn semantic_tokens(&self, params: SemanticTokensParams) -> Option<SemanticTokensResult> {
+        let parser = self.documents.get(&params.text_document.uri)?;
+        let data = encode_delta(&semantic_tokens(parser.text()))
+            .chunks_exact(5)
+            .map(|t| SemanticToken {
+                delta_line: t[0],
+                delta_start: t[1],
+                length: t[2],
+                token_type: t[3],
+                token_modifiers_bitset: t[4],
+            })
+            .collect();
+        Some(SemanticTokensResult::Tokens(SemanticTokens {
+            result_id: None,
+            data,
+        }))
+    }
+
+    fn formatting(&self, params: DocumentFormattingParams) -> Option<Vec<LspTextEdit>> {
+        let parser = self.documents.get(&params.text_document.uri)?;
+        let text = parser.text();
+        let formatted = format(text);
+        if formatted == text {
+            return Some(Vec::new());
+        }
+        let index = LineIndex::new(text);
+        Some(vec![LspTextEdit {
+            range: range(&index, &(0..text.len())),
+            new_text: formatted,
+        }])
+    }
+
+    fn symbols(&self, params: DocumentSymbolParams) -> Option<DocumentSymbolResponse> {
+        let parser = self.documents.get(&params.text_document.uri)?;
+        let text = parser.text();
+        let index = LineIndex::new(text);
+        let symbols = directives(parser.document())
+            .map(|d| symbol(&index, d))
+            .collect();
+        Some(DocumentSymbolResponse::Nested(symbols))
+    }
+}
+
+/// Every diagnostic of the document at `uri` in `parser`, whose text is
+/// `text`. The files a `file:` document includes are read from disk so their
+/// variables are defined; only the document's own diagnostics are kept.
+fn check(
+    uri: &Uri,
+    text: &str,
+    parser: &IncrementalParser,
+    options: &ValidateOptions,
+) -> Vec<Diagnostic> {
+    if let Some(path) = file_path(uri)
+        && directives(parser.document()).any(|d| d.name == INCLUDE)
+    {
+        let bundle = parse_with_includes(&path, text, &FsProvider);
+        let mut diagnostics = bundle.validate_with(options);
+        diagnostics.extend(bundle.diagnostics);
+        diagnostics.retain(|d| d.file == FileId(0));
+        diagnostics.sort_by_key(|d| d.span.start);
+        return diagnostics;
+    }
+    let mut diagnostics = parser.diagnostics().to_vec();
+    let (document, evaluated) = evaluate(text, parser.document());
+    diagnostics.extend(evaluated);
+    diagnostics.extend(validate_document_with(text, &document, options));
+    diagnostics
+}
+
+/// The file system path of a `file:` URI.
+fn file_path(uri: &Uri) -> Option<String> {
+    let scheme = uri.scheme()?;
+    if !scheme.as_str().eq_ignore_ascii_case("file") {
+        return None;
+    }
+    Some(
+        uri.path()
+            .as_estr()
+            .decode()
+            .into_string_lossy()
+            .into_owned(),
+    )
+}
+
+fn to_lsp(uri: &Uri, index: &LineIndex, diagnostic: &Diagnostic) -> lsp_types::Diagnostic {
+    let mut message = diagnostic.message.clone();
+    if !diagnostic.expected.is_empty() {
+        message.push_str(&format!("\nexpected {}", diagnostic.expected.join(" or ")));
+    }
+    if let Some(help) = &diagnostic.help {
+        message.push_str(&format!("\nhelp: {help}"));
+    }
+    let related: Vec<_> = diagnostic
+        .related
+        .iter()
+        .map(|r| DiagnosticRelatedInformation {
+            location: Location {
+                uri: uri.clone(),
+                range: range(index, &r.span),
+            },
+            message: r.message.clone(),
+        })
+        .collect();
+    lsp_types::Diagnostic {
+        range: range(index, &diagnostic.span),
+        severity: Some(match diagnostic.severity {
+            Severity::Error => DiagnosticSeverity::ERROR,
+            Severity::Warning => DiagnosticSeverity::WARNING,
+            Severity::Info => DiagnosticSeverity::INFORMATION,
+            Severity::Hint => DiagnosticSeverity::HINT,
+        }),
+        code: Some(NumberOrString::String(diagnostic.code.to_string())),
+        source: Some(env!("CARGO_PKG_NAME").to_string()),
+        message,
+        related_information: (!related.is_empty()).then_some(related),
+        ..lsp_types::Diagnostic::default()
+    }
+}
+
+fn position(index: &LineIndex, offset: usize) -> Position {
+    let at = index.line_col(offset, Encoding::Utf16);
+    Position::new(at.line, at.col)
+}
+
+fn range(index: &LineIndex, span: &Span) -> Range {
+    Range::new(position(index, span.start), position(index, span.end))
+}
+
+/// The byte offset of `position`; past the last line is the end of the text.
+fn offset(index: &LineIndex, position: Position) -> usize {
+    let at = LineCol {
+        line: position.line,
+        col: position.character,
+    };
+    index.offset(at, Encoding::Utf16).unwrap_or(index.len())
+}
+
+/// Like [`offset`], but only indexes the line `position` is on, so applying
+/// an edit does not scan the whole document.
+fn edit_offset(parser: &IncrementalParser, position: Position) -> usize {
+    match parser.line(position.line as usize) {
+        Some((start, line)) => {
+            let at = LineCol {
+                line: 0,
+                col: position.character,
+            };
+            start
+                + LineIndex::new(line)
+                    .offset(at, Encoding::Utf16)
+                    .unwrap_or(line.len())
+        }
+        None => parser.text().len(),
+    }
+}
+
+/// Whether `offset` is in `span` or right after it, where the cursor sits
+/// after typing it.
+fn contains(span: &Span, offset: usize) -> bool {
+    span.start <= offset && offset <= span.end
+}
+
+fn directives(doc: &Document) -> impl Iterator<Item = &Directive> {
+    doc.headers
+        .iter()
+        .chain(doc.body.iter().filter_map(|node| match &node.kind {
+            BodyKind::Directive(d) => Some(d),
+            _ => None,
+        }))
+}
+
+/// Completions at byte `offset` of `text`: directive names after `@`, keys
+/// after a directive name, and the allowed values after `key=`.
+fn complete(text: &str, doc: &Document, offset: usize) -> Vec<CompletionItem> {
+    let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let before = &text[line_start..offset];
+    let indent = before.len() - before.trim_start().len();
+    let Some(line) = before.trim_start().strip_prefix('@') else {
+        return Vec::new();
+    };
+    let Some((name, rest)) = line.split_once(char::is_whitespace) else {
+        // Still typing the name. The first directive after any `@define` and
+        // `@include` lines declares the question, so only question types and
+        // those go there.
+        let first = !directives(doc).any(|d| d.span.start < line_start && !is_preamble(d))
+            && doc
+                .body
+                .iter()
+                .all(|n| n.span.start >= line_start || matches!(n.kind, BodyKind::Blank));
+        let names: Vec<&str> = if first {
+            let preamble = SCHEMAS.iter().filter(|s| s.preamble).map(|s| s.name);
+            Import::VARIANTS.iter().copied().chain(preamble).collect()
+        } else {
+            SCHEMAS
+                .iter()
+                .filter(|s| !s.first)
+                .map(|s| s.name)
+                .collect()
+        };
+        return names
+            .into_iter()
+            .filter_map(schema_for)
+            .map(|schema| CompletionItem {
+                kind: Some(CompletionItemKind::KEYWORD),
+                documentation: Some(markdown(describe_directive(schema))),
+                ..CompletionItem::new_simple(schema.name.to_string(), schema.doc.to_string())
+            })
+            .collect();
+    };
+    let Some(schema) = schema_for(name) else {
+        return Vec::new();
+    };
+    if rest.matches('"').count() % 2 == 1 || rest.matches('\'').count() % 2 == 1 {
+        // Inside a quoted value.
+        return Vec::new();
+    }
+    let word = &rest[rest.rfind(char::is_whitespace).map_or(0, |i| i + 1)..];
+    if let Some((key, _)) = word.split_once('=') {
+        let Some(param) = schema.param(key) else {
+            return Vec::new();
+        };
+        let values = match param.ty {
+            ParamType::Bool => &["true", "false"][..],
+            _ => param.allowed,
+        };
+        return values
+            .iter()
+            .map(|value| CompletionItem {
+                kind: Some(CompletionItemKind::VALUE),
+                ..CompletionItem::new_simple(value.to_string(), param.ty.describe().to_string())
+            })
+            .collect();
+    }
+    let directive = directives(doc).find(|d| d.span.start == line_start + indent);
+    let given =
+        |param: &&ParamSpec| directive.is_some_and(|d| d.pairs.iter().any(|p| p.key == param.name));
+    schema
+        .params
+        .iter()
+        .filter(|p| !given(p))
+        .map(|param| CompletionItem {
+            kind: Some(CompletionItemKind::PROPERTY),
+            documentation: Some(markdown(describe_param(param))),
+            insert_text: Some(format!("{}=", param.name)),
+            ..CompletionItem::new_simple(param.name.to_string(), param.ty.describe().to_string())
+        })
+        .collect()
+}
+
+fn markdown(value: String) -> Documentation {
+    Documentation::MarkupContent(MarkupContent {
+        kind: MarkupKind::Markdown,
+        value,
+    })
+}
+
+/// Hover text of a directive: its doc and its parameters.
+fn describe_directive(schema: &DirectiveSchema) -> String {
+    let mut out = format!("**@{}**\n\n{}", schema.name, schema.doc);
+    if !schema.params.is_empty() {
+        out.push_str("\n\n");
+        for param in schema.params {
+            let required = if param.required { ", required" } else { "" };
+            out.push_str(&format!(
+                "- `{}`: {}{required}\n",
+                param.name,
+                param.ty.describe()
+            ));
+        }
+    }
+    out
+}
+
+/// Hover text of a parameter: its type, default, allowed values and doc.
+fn describe_param(param: &ParamSpec) -> String {
+    let mut out = format!("**{}**: {}", param.name, param.ty.describe());
+    if param.required {
+        out.push_str(", required");
+    }
+    if let Some(default) = param.default {
+        out.push_str(&format!(", default `{default}`"));
+    }
+    if !param.allowed.is_empty() {
+        let allowed: Vec<String> = param.allowed.iter().map(|a| format!("`{a}`")).collect();
+        out.push_str(&format!("\n\nOne of {}.", allowed.join(", ")));
+    }
+    out.push_str(&format!("\n\n{}", param.doc));
+    out
+}
+
+/// A directive as an outline entry, with its pairs as children.
+fn symbol(index: &LineIndex, directive: &Directive) -> DocumentSymbol {
+    let schema = schema_for(&directive.name);
+    let prompt = directive
+        .args
+        .first()
+        .map(|a| &a.value)
+        .or(directive.get("prompt").map(|p| &p.value));
+    let children = directive
+        .pairs
+        .iter()
+        .map(|pair| {
+            let span = pair.key_span.start..pair.value_span.end.max(pair.key_span.end);
+            #[allow(deprecated)]
+            DocumentSymbol {
+                name: pair.key.clone(),
+                detail: Some(pair.value.to_string()),
+                kind: if directive.name == DEFINE {
+                    SymbolKind::VARIABLE
+                } else {
+                    SymbolKind::FIELD
+                },
+                tags: None,
+                deprecated: None,
+                range: range(index, &span),
+                selection_range: range(index, &pair.key_span),
+                children: None,
+            }
+        })
+        .collect();
+    #[allow(deprecated)]
+    DocumentSymbol {
+        name: format!("@{}", directive.name),
+        detail: prompt.map(|v| match v {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        }),
+        kind: if schema.is_some_and(|s| s.first) {
+            SymbolKind::CLASS
+        } else {
+            SymbolKind::OBJECT
+        },
+        tags: None,
+        deprecated: None,
+        range: range(index, &directive.span),
+        selection_range: range(index, &directive.name_span),
+        children: Some(children),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::JoinHandle;
+
+    use lsp_server::RequestId;
+    use lsp_types::notification::{Exit, Initialized};
+    use lsp_types::request::{Initialize, Shutdown};
+    use serde_json::{Value as Json, json};
+
+    use super::*;
+    use crate::schema::DuplicateKeys;
+
+    const URI: &str = "file:///bank/q1.q";
+
+    /// An editor driving a server over an in-memory connection.
+    struct Client {
+        connection: Connection,
+        server: JoinHandle<()>,
+        next_id: i32,
+    }
+
+    impl Client {
+        fn start() -> Client {
+            Client::start_with(ValidateOptions::default())
+        }
+
+        fn start_with(options: ValidateOptions) -> Client {
+            let (client, server) = Connection::memory();
+            let server = std::thread::spawn(move || serve_with(&server, options).unwrap());
+            let mut client = Client {
+                connection: client,
+                server,
+                next_id: 0,
+            };
+            let result = client.request::<Initialize>(json!({ "capabilities": {} }));
+            assert_eq!(result["capabilities"]["positionEncoding"], "utf-16");
+            client.notify::<Initialized>(json!({}));
+            client
+        }
+
+        fn request<R: LspRequest>(&mut self, params: Json) -> Json {
+            self.next_id += 1;
+            let id = RequestId::from(self.next_id);
+            let request = Request::new(id.clone(), R::METHOD.to_string(), params);
+            self.connection.sender.send(request.into()).unwrap();
+            loop {
+                match self.connection.receiver.recv().unwrap() {
+                    Message::Response(response) if response.id == id => {
+                        assert!(response.error.is_none(), "{:?}", response.error);
+                        return response.result.unwrap_or_default();
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        fn notify<N: LspNotification>(&self, params: Json) {
+            let notification = Notification::new(N::METHOD.to_string(), params);
+            self.connection.sender.send(notification.into()).unwrap();
+        }
+
+        fn open(&self, text: &str) -> Json {
+            self.open_at(URI, text)
+        }
+
+        fn open_at(&self, uri: &str, text: &str) -> Json {
+            self.notify::<DidOpenTextDocument>(json!({
+                "textDocument": { "uri": uri, "languageId": "q", "version": 1, "text": text }
+            }));
+            self.diagnostics_of(uri)
+        }
+
+        /// The next published diagnostics.
+        fn diagnostics(&self) -> Json {
+            self.diagnostics_of(URI)
+        }
+
+        fn diagnostics_of(&self, uri: &str) -> Json {
+            loop {
+                if let Message::Notification(n) = self.connection.receiver.recv().unwrap()
+                    && n.method == PublishDiagnostics::METHOD
+                {
+                    assert_eq!(n.params["uri"], uri);
+                    return n.params["diagnostics"].clone();
+                }
+            }
+        }
+
+        fn at(&mut self, method: &str, line: u32, character: u32) -> Json {
+            let params = json!({
+                "textDocument": { "uri": URI },
+                "position": { "line": line, "character": character },
+            });
+            match method {
+                "completion" => self.request::<Completion>(params),
+                _ => self.request::<HoverRequest>(params),
+            }
+        }
+
+        fn shutdown(mut self) {
+            self.request::<Shutdown>(Json::Null);
+            self.notify::<Exit>(Json::Null);
+            self.server.join().unwrap();
+        }
+    }
+
+    fn labels(items: &Json) -> Vec<&str> {
+        items
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|i| i["label"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn publishes_diagnostics_on_change() {
+        let mut client = Client::start();
+        let diagnostics = client.open("@option Pilih points=1 points=x\n* a\n- b\n");
+        let codes: Vec<&str> = diagnostics
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| d["code"].as_str().unwrap())
+            .collect();
+        assert_eq!(codes, ["E0110", "E0104"]);
+        let duplicate = &diagnostics[0];
+        assert_eq!(
+            duplicate["range"],
+            json!({ "start": { "line": 0, "character": 23 }, "end": { "line": 0, "character": 29 } })
+        );
+        assert_eq!(
+            duplicate["message"],
+            "duplicate key `points` for `@option`\nhelp: remove one of them"
+        );
+        let related = &duplicate["relatedInformation"][0];
+        assert_eq!(related["message"], "first used here");
+        assert_eq!(related["location"]["uri"], URI);
+        assert_eq!(related["location"]["range"]["start"]["character"], 14);
+
+        // Delete ` points=x`.
+        client.notify::<DidChangeTextDocument>(json!({
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{
+                "range": { "start": { "line": 0, "character": 22 }, "end": { "line": 0, "character": 31 } },
+                "text": "",
+            }],
+        }));
+        assert_eq!(client.diagnostics(), json!([]));
+        let result = client.request::<Formatting>(json!({
+            "textDocument": { "uri": URI },
+            "options": { "tabSize": 4, "insertSpaces": true },
+        }));
+        assert_eq!(result, json!([]));
+
+        client.notify::<DidCloseTextDocument>(json!({ "textDocument": { "uri": URI } }));
+        assert_eq!(client.diagnostics(), json!([]));
+        client.shutdown();
+
+        let client = Client::start_with(ValidateOptions {
+            duplicate_keys: DuplicateKeys::LastWins,
+        });
+        let diagnostics = client.open("@option Pilih points=1 points=x\n* a\n- b\n");
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        assert_eq!(diagnostics[0]["code"], "E0104");
+        client.shutdown();
+    }
+
+    #[test]
+    fn reads_included_files_from_disk() {
+        let dir = std::env::temp_dir().join(format!("lsp-include-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("defs.q"),
+            "@define course=CS101\n@meta tags=[x y\n",
+        )
+        .unwrap();
+        let uri = format!("file://{}/q1.q", dir.display());
+        let client = Client::start();
+        let diagnostics = client.open_at(
+            &uri,
+            "@option\n@include path=defs.q\n${course}: ibu kota?\n* a\n- b\n",
+        );
+        // The error inside defs.q belongs to another file.
+        assert_eq!(diagnostics, json!([]));
+        let diagnostics = client.open_at(&uri, "@option\n${course}?\n* a\n- b\n");
+        assert_eq!(diagnostics[0]["code"], "E0401");
+        client.shutdown();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn answers_requests() {
+        let mut client = Client::start();
+        client.open("@op\n@meta   tags=[x] difficulty=\n@m\n  Which?\n* a\n- b\n");
+
+        let mut first = Import::VARIANTS.to_vec();
+        first.extend(["include", "define"]);
+        assert_eq!(labels(&client.at("completion", 0, 3)), first);
+        let items = client.at("completion", 2, 2);
+        let names = labels(&items);
+        assert!(
+            names.contains(&"meta") && !names.contains(&"option"),
+            "{names:?}"
+        );
+        assert_eq!(labels(&client.at("completion", 1, 8)), ["author"]);
+        assert_eq!(
+            labels(&client.at("completion", 1, 29)),
+            ["easy", "medium", "hard"]
+        );
+
+        let hover = client.at("hover", 1, 18);
+        let value = hover["contents"]["value"].as_str().unwrap();
+        assert!(value.starts_with("**difficulty**: a string"), "{value}");
+        assert!(value.contains("`easy`, `medium`, `hard`"), "{value}");
+        assert_eq!(
+            hover["range"]["start"],
+            json!({ "line": 1, "character": 17 })
+        );
+        let hover = client.at("hover", 1, 2);
+        let value = hover["contents"]["value"].as_str().unwrap();
+        assert!(value.starts_with("**@meta**\n\nInformation"), "{value}");
+
+        let params = json!({ "textDocument": { "uri": URI } });
+        let tokens = client.request::<SemanticTokensFullRequest>(params.clone());
+        let data = tokens["data"].as_array().unwrap();
+        // The `@` of `@op` comes first: line 0, column 0, one long, a directive.
+        assert_eq!(data[..4], [json!(0), json!(0), json!(1), json!(0)]);
+
+        let symbols = client.request::<DocumentSymbolRequest>(params.clone());
+        assert_eq!(symbols[1]["name"], "@meta");
+        assert_eq!(symbols[1]["children"][0]["name"], "tags");
+        assert_eq!(symbols[1]["children"][0]["detail"], "[x]");
+
+        let edits = client.request::<Formatting>(json!({
+            "textDocument": { "uri": URI },
+            "options": { "tabSize": 4, "insertSpaces": true },
+        }));
+        assert!(
+            edits[0]["newText"]
+                .as_str()
+                .unwrap()
+                .contains("@m\nWhich?\n"),
+            "{edits}"
+        );
+
+        // The question directive may still follow a preamble.
+        client.open("@define n=1\n@include path=x.q\n@o\n");
+        assert_eq!(labels(&client.at("completion", 2, 2)), first);
+        client.shutdown();
+    }
+}
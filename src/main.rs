@@ -1,3 +1,17 @@
-fn main() {
-    println!("Hello, world!");
+// The command line is native only: its dependencies are not built for wasm32.
+#[cfg(not(target_arch = "wasm32"))]
+fn main() -> std::process::ExitCode {
+    use clap::Parser;
+    use web_assembly::cli::{Cli, run};
+
+    let code = run(
+        Cli::parse(),
+        &mut std::io::stdin(),
+        &mut std::io::stdout(),
+        &mut std::io::stderr(),
+    );
+    std::process::ExitCode::from(code as u8)
 }
+
+#[cfg(target_arch = "wasm32")]
+fn main() {}
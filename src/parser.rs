@@ -1,66 +1,734 @@
-use chumsky::prelude::*;
+use chumsky::{Stream, error::SimpleReason, prelude::*};
+use rowan::TextRange;
+use serde::Serialize;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+use crate::diagnostic::{Diagnostic, codes};
+use crate::eval::interpolations;
+use crate::line_index::{Encoding, LineIndex};
+use crate::syntax::SyntaxKind;
+use crate::syntax::ast::{AstNode, Root};
+use crate::value::Value;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum ParsedFirstLine {
     Empty,
     Directive {
         name: String,
-        pairs: Vec<(String, String)>,
+        args: Vec<Value>,
+        pairs: Vec<(String, Value)>,
     },
 }
 
-fn directive_parser() -> impl Parser<char, ParsedFirstLine, Error = Simple<char>> {
-    let ident = text::ident();
-    let value = none_of([' ', '\t', '\n'])
-        .repeated()
+/// Byte range into the parsed source.
+pub type Span = std::ops::Range<usize>;
+
+/// A `key=value` pair of a directive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pair {
+    pub key: String,
+    pub value: Value,
+    pub key_span: Span,
+    pub value_span: Span,
+}
+
+/// A positional argument of a directive, such as the prompt in
+/// `@option "What is 2+2?" points=1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Arg {
+    pub value: Value,
+    pub span: Span,
+}
+
+/// A single `@name arg ... key=value ...` line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Directive {
+    pub name: String,
+    pub name_span: Span,
+    /// Positional arguments, in source order.
+    pub args: Vec<Arg>,
+    pub pairs: Vec<Pair>,
+    pub span: Span,
+    /// Regions the grammar could not place, each covered by a diagnostic.
+    /// Only directives recovered from a broken line have any; see
+    /// [`parse_header_recovering`].
+    pub errors: Vec<Span>,
+}
+
+impl From<Directive> for ParsedFirstLine {
+    fn from(directive: Directive) -> Self {
+        ParsedFirstLine::Directive {
+            name: directive.name,
+            args: directive.args.into_iter().map(|a| a.value).collect(),
+            pairs: directive
+                .pairs
+                .into_iter()
+                .map(|p| (p.key, p.value))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BodyKind {
+    /// Empty or whitespace-only line.
+    Blank,
+    /// Any line not starting with `@`; holds the line without surrounding whitespace.
+    Text(String),
+    /// A directive line below the header block.
+    Directive(Directive),
+}
+
+/// One line below the header block. `span` covers the line without its terminator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BodyNode {
+    pub kind: BodyKind,
+    pub span: Span,
+}
+
+/// A whole parsed document: the header block plus one node per later line.
+///
+/// The header block is the run of directive lines starting at the first
+/// non-empty line; the first line that is not a directive starts the body.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Document {
+    /// The header block's directives, in source order.
+    pub headers: Vec<Directive>,
+    pub body: Vec<BodyNode>,
+}
+
+impl Document {
+    /// The first header directive.
+    pub fn header(&self) -> Option<&Directive> {
+        self.headers.first()
+    }
+}
+
+/// One argument of a directive line, in source order.
+enum Argument {
+    Positional(Arg),
+    Named(Pair),
+}
+
+fn directive_parser() -> impl Parser<char, Directive, Error = Simple<char>> {
+    let ident = text::ident().map_with_span(|s: String, span: Span| (s, span));
+    let value = value_parser().map_with_span(|v, span: Span| (v, span));
+    let missing = one_of(" \t").ignored().or(end()).rewind().to(None);
+    // `key= # note`: a comment after the `=` means the value is missing.
+    let commented = text::whitespace()
         .at_least(1)
-        .collect::<String>();
-    let pair = ident.clone().then_ignore(just('=').padded()).then(value);
+        .then(comment())
+        .rewind()
+        .to(None);
+    let pair = ident
+        .labelled("key")
+        .then_ignore(text::whitespace().then(just('=').labelled("`=`")))
+        .then(commented.or(text::whitespace().ignore_then(value.map(Some).or(missing))))
+        .validate(|((key, key_span), value), span: Span, emit| {
+            let (value, value_span) = value.unwrap_or_else(|| {
+                emit(Simple::expected_input_found(span.clone(), None, None).with_label("value"));
+                (Value::String(String::new()), span.end..span.end)
+            });
+            Pair {
+                key,
+                value,
+                key_span,
+                value_span,
+            }
+        });
+    let argument =
+        pair.map(Argument::Named).or(positional_parser()
+            .map_with_span(|value, span| Argument::Positional(Arg { value, span })));
     just('@')
-        .ignore_then(ident.map(|s: String| s))
-        .then(pair.padded().repeated())
+        .ignore_then(ident.labelled("directive name"))
+        .then(text::whitespace().ignore_then(argument).repeated())
+        .map_with_span(|((name, name_span), arguments), span| {
+            let (mut args, mut pairs) = (Vec::new(), Vec::new());
+            for argument in arguments {
+                match argument {
+                    Argument::Positional(arg) => args.push(arg),
+                    Argument::Named(pair) => pairs.push(pair),
+                }
+            }
+            Directive {
+                name,
+                name_span,
+                args,
+                pairs,
+                span,
+                errors: Vec::new(),
+            }
+        })
+        .then_ignore(text::whitespace().at_least(1).then(comment()).or_not())
+        .then_ignore(text::whitespace())
         .then_ignore(end())
-        .map(|(name, pairs_vec)| ParsedFirstLine::Directive {
-            name,
-            pairs: pairs_vec,
+}
+
+/// A `#` or `//` comment running to the end of the line. Callers only try it
+/// at the start of a line or after whitespace.
+fn comment() -> impl Parser<char, String, Error = Simple<char>> + Clone {
+    just('#')
+        .chain(any().repeated())
+        .or(just('/').chain(just('/')).chain(any().repeated()))
+        .collect()
+}
+
+/// Whether a trimmed line is a whole-line comment.
+pub(crate) fn is_comment_line(trimmed: &str) -> bool {
+    trimmed.starts_with('#') || trimmed.starts_with("//")
+}
+
+/// A pair value: a quoted string, a `[a, b]` list, a `{k=v}` map or a bare word
+/// classified by [`Value::from_bare`]. Bare words inside lists and maps stop at
+/// `,`, `]` and `}`; top-level bare words only stop at whitespace.
+fn value_parser() -> impl Parser<char, Value, Error = Simple<char>> {
+    let bare = none_of([' ', '\t', '\n', '"', '\'', '[', '{'])
+        .chain(none_of([' ', '\t', '\n']).repeated())
+        .collect::<String>()
+        .map(|s| Value::from_bare(&s));
+    bare.or(nested_value())
+}
+
+/// A positional argument: a quoted string, list or map, or a bare word. Bare
+/// words may not contain `=`, which would make them a pair, nor start a comment.
+fn positional_parser() -> impl Parser<char, Value, Error = Simple<char>> {
+    let delimited = one_of("\"'[{").rewind().ignore_then(nested_value());
+    let bare = just('/')
+        .then(just('/'))
+        .not()
+        .rewind()
+        .ignore_then(none_of([' ', '\t', '\n', '"', '\'', '[', '{', '=', '#']))
+        .chain(none_of([' ', '\t', '\n', '=']).repeated())
+        .collect::<String>()
+        .map(|s| Value::from_bare(&s));
+    delimited
+        .or(bare)
+        .then_ignore(one_of(" \t").ignored().or(end()).rewind())
+}
+
+/// A value inside a list or map, or a top-level value that starts with a
+/// quote or bracket.
+fn nested_value() -> impl Parser<char, Value, Error = Simple<char>> + Clone {
+    // `${name}` references may contain the braces nested bare words stop at.
+    let interpolation = just('$')
+        .ignore_then(text::ident().delimited_by(just('{'), just('}')))
+        .map(|name: String| format!("${{{name}}}").chars().collect::<Vec<char>>());
+    recursive(|nested| {
+        let bare = interpolation
+            .or(none_of([' ', '\t', '\n', '"', '\'', '[', '{', ',', ']', '}']).map(|c| vec![c]))
+            .chain::<char, _, _>(
+                interpolation
+                    .or(none_of([' ', '\t', '\n', ',', ']', '}']).map(|c| vec![c]))
+                    .repeated()
+                    .flatten(),
+            )
+            .collect::<String>()
+            .map(|s| Value::from_bare(&s));
+        // As in the lexer, `#` or `//` after whitespace starts a comment, so
+        // it cannot start a value there.
+        let comment_start = just('#').or(just('/').then_ignore(just('/')));
+        let gap = text::whitespace()
+            .at_least(1)
+            .then(comment_start.not().rewind())
+            .or_not();
+        let element = gap
+            .ignore_then(nested.clone())
+            .then_ignore(text::whitespace());
+        let list = element
+            .separated_by(just(','))
+            .allow_trailing()
+            .then_ignore(text::whitespace())
+            .delimited_by(just('['), just(']').labelled("`]`"))
+            .map(Value::List);
+        let entry = text::ident()
+            .then_ignore(text::whitespace().then(just('=')))
+            .then(gap.ignore_then(nested))
+            .padded();
+        let map = entry
+            .separated_by(just(','))
+            .allow_trailing()
+            .padded()
+            .delimited_by(just('{'), just('}').labelled("`}`"))
+            .map(Value::Map);
+        choice((quoted_string().map(Value::String), list, map, bare))
+    })
+}
+
+/// A `"..."` or `'...'` string, unescaped. Supports `\"`, `\'`, `\\`, `\n`,
+/// `\t` and `\u{..}` escapes.
+fn quoted_string() -> impl Parser<char, String, Error = Simple<char>> + Clone {
+    let unicode = just('u').ignore_then(
+        filter(|c: &char| c.is_ascii_hexdigit())
+            .repeated()
+            .at_least(1)
+            .at_most(6)
+            .collect::<String>()
+            .delimited_by(just('{'), just('}'))
+            .validate(|digits, span: Span, emit| {
+                u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .unwrap_or_else(|| {
+                        emit(Simple::custom(
+                            span,
+                            format!("invalid unicode escape `\\u{{{digits}}}`"),
+                        ));
+                        char::REPLACEMENT_CHARACTER
+                    })
+            }),
+    );
+    let escape = just('\\').ignore_then(
+        choice((
+            just('"'),
+            just('\''),
+            just('\\'),
+            just('n').to('\n'),
+            just('t').to('\t'),
+            unicode,
+        ))
+        .labelled("escape sequence"),
+    );
+    let quoted = |quote: char| {
+        just(quote).ignore_then(
+            escape
+                .or(none_of([quote, '\\', '\n']))
+                .repeated()
+                .collect::<String>()
+                .then_ignore(just(quote).labelled("closing quote")),
+        )
+    };
+    quoted('"').or(quoted('\''))
+}
+
+/// Parse one trimmed directive line on its own; spans are relative to `line`.
+pub(crate) fn parse_directive(line: &str) -> Option<Directive> {
+    parse_at(&directive_parser(), line, 0).ok()
+}
+
+/// Parse a standalone value literal, e.g. the text of a CST value node.
+pub(crate) fn parse_value(text: &str) -> Option<Value> {
+    parse_at(&value_parser().then_ignore(end()), text, 0).ok()
+}
+
+/// Best-effort directive for a trimmed directive `line` the grammar rejected,
+/// read off its lossless tree. Arguments and pairs keep the values that parse;
+/// a missing or broken value is an empty string whose span is listed in
+/// [`Directive::errors`], as are a missing name and text that fits nowhere.
+/// Spans are shifted by `base`.
+fn recover_directive(line: &str, base: usize) -> Directive {
+    let root = Root::parse(line);
+    let node = root.header().expect("line starts with `@`");
+    let range = |r: TextRange| base + usize::from(r.start())..base + usize::from(r.end());
+    let mut errors = Vec::new();
+    let name_span = match node.name() {
+        Some(name) => range(name.text_range()),
+        None => {
+            errors.push(base + 1..base + 1);
+            base + 1..base + 1
+        }
+    };
+    let args = node
+        .args()
+        .map(|arg| {
+            let span = range(arg.syntax().text_range());
+            let value = arg.to_value().unwrap_or_else(|| {
+                errors.push(span.clone());
+                Value::String(String::new())
+            });
+            Arg { value, span }
         })
+        .collect();
+    let pairs = node
+        .pairs()
+        .filter_map(|pair| {
+            let key = pair.key()?;
+            let parsed = pair
+                .value()
+                .map(|v| (v.to_value(), range(v.syntax().text_range())));
+            let (value, value_span) = match parsed {
+                Some((Some(value), span)) => (value, span),
+                Some((None, span)) => {
+                    errors.push(span.clone());
+                    (Value::String(String::new()), span)
+                }
+                None => {
+                    let end = range(pair.syntax().text_range()).end;
+                    errors.push(end..end);
+                    (Value::String(String::new()), end..end)
+                }
+            };
+            Some(Pair {
+                key: key.text().to_string(),
+                value,
+                key_span: range(key.text_range()),
+                value_span,
+            })
+        })
+        .collect();
+    errors.extend(
+        node.syntax()
+            .descendants()
+            .filter(|n| n.kind() == SyntaxKind::Error)
+            .map(|n| range(n.text_range())),
+    );
+    errors.sort_by_key(|span| (span.start, span.end));
+    errors.dedup();
+    Directive {
+        name: node
+            .name()
+            .map(|t| t.text().to_string())
+            .unwrap_or_default(),
+        name_span,
+        args,
+        pairs,
+        span: base..base + line.len(),
+        errors,
+    }
 }
 
-pub fn parse_first_line(src: &str) -> Result<ParsedFirstLine, String> {
-    let mut first_non_empty = None;
-    for line in src.lines() {
-        if !line.trim().is_empty() {
-            first_non_empty = Some(line.trim());
-            break;
+/// Run `parser` over `line`, reporting spans as byte offsets shifted by `base`.
+fn parse_at<O>(
+    parser: &impl Parser<char, O, Error = Simple<char>>,
+    line: &str,
+    base: usize,
+) -> Result<O, Vec<Simple<char>>> {
+    let eoi = base + line.len();
+    parser.parse(Stream::from_iter(
+        eoi..eoi,
+        line.char_indices()
+            .map(move |(i, c)| (c, base + i..base + i + c.len_utf8())),
+    ))
+}
+
+fn describe(token: Option<&char>) -> String {
+    match token {
+        Some(c) => format!("`{c}`"),
+        None => "end of line".into(),
+    }
+}
+
+/// Convert a chumsky error into a [`Diagnostic`] located in `src`.
+fn to_diagnostic(src: &str, err: Simple<char>) -> Diagnostic {
+    let span = err.span();
+    let found = describe(err.found());
+    let mut expected: Vec<String> = match err.label() {
+        Some(label) => vec![label.to_string()],
+        None => err.expected().map(|e| describe(e.as_ref())).collect(),
+    };
+    expected.sort();
+    expected.dedup();
+    match err.reason() {
+        SimpleReason::Unclosed { span, delimiter } => Diagnostic::error(
+            src,
+            codes::UNCLOSED,
+            span.clone(),
+            format!("unclosed delimiter `{delimiter}`"),
+        )
+        .with_found(found),
+        SimpleReason::Custom(msg) => Diagnostic::error(src, codes::SYNTAX, span, msg.clone()),
+        SimpleReason::Unexpected if err.label() == Some("value") && err.found().is_none() => {
+            let key = src[span.clone()].split('=').next().unwrap_or("").trim();
+            Diagnostic::error(
+                src,
+                codes::MISSING_VALUE,
+                span,
+                format!("missing value for `{key}`"),
+            )
+            .with_expected(expected)
+            .with_found(found)
+            .with_help(format!("write a value after `=`, e.g. `{key}=1`"))
+        }
+        SimpleReason::Unexpected => {
+            let message = if expected.is_empty() {
+                format!("unexpected {found}")
+            } else {
+                format!("expected {}, found {found}", expected.join(" or "))
+            };
+            Diagnostic::error(src, codes::SYNTAX, span, message)
+                .with_expected(expected)
+                .with_found(found)
         }
     }
-    let Some(line) = first_non_empty else {
-        return Ok(ParsedFirstLine::Empty);
+}
+
+fn missing_header(src: &str, span: Span) -> Diagnostic {
+    Diagnostic::error(
+        src,
+        codes::MISSING_HEADER,
+        span,
+        "first non-empty line must start with `@`",
+    )
+    .with_expected(vec!["`@`".into()])
+    .with_help("start the document with a directive such as `@option`")
+}
+
+/// Split `src` into lines (without `\n` / `\r\n`) paired with their byte offset.
+pub(crate) fn lines_with_offsets(src: &str) -> impl Iterator<Item = (usize, &str)> {
+    src.split_inclusive('\n').scan(0usize, |offset, raw| {
+        let start = *offset;
+        *offset += raw.len();
+        let line = raw.strip_suffix('\n').unwrap_or(raw);
+        Some((start, line.strip_suffix('\r').unwrap_or(line)))
+    })
+}
+
+/// Parse the first non-empty line of `src` as a directive, keeping spans.
+/// Comment lines are skipped. Returns `Ok(None)` when `src` has no other line.
+pub fn parse_header(src: &str) -> Result<Option<Directive>, Vec<Diagnostic>> {
+    match parse_header_recovering(src) {
+        (header, diagnostics) if diagnostics.is_empty() => Ok(header),
+        (_, diagnostics) => Err(diagnostics),
+    }
+}
+
+/// Like [`parse_header`], but a broken directive line still yields a
+/// best-effort directive alongside its diagnostics, so editors can keep
+/// completing, highlighting and validating half-typed input. Returns no
+/// directive only when `src` is empty or does not start with one.
+pub fn parse_header_recovering(src: &str) -> (Option<Directive>, Vec<Diagnostic>) {
+    let first = lines_with_offsets(src).find(|(_, l)| {
+        let l = l.trim();
+        !l.is_empty() && !is_comment_line(l)
+    });
+    let Some((offset, line)) = first else {
+        return (None, Vec::new());
     };
+    let start = offset + (line.len() - line.trim_start().len());
+    let line = line.trim();
     if !line.starts_with('@') {
-        return Err("First non-empty line must start with @".into());
+        return (None, vec![missing_header(src, start..start + line.len())]);
     }
-    match directive_parser().parse(line) {
-        Ok(ast) => Ok(ast),
-        Err(errs) => {
-            let msg = errs
-                .into_iter()
-                .map(|e| format!("{}", e))
-                .collect::<Vec<_>>()
-                .join(", ");
-            Err(format!("DSL parse error: {msg}"))
+    match parse_at(&directive_parser(), line, start) {
+        Ok(directive) => (Some(directive), Vec::new()),
+        Err(errs) => (
+            Some(recover_directive(line, start)),
+            errs.into_iter().map(|e| to_diagnostic(src, e)).collect(),
+        ),
+    }
+}
+
+/// Parse only the first non-empty line of `src` as a directive.
+pub fn parse_first_line(src: &str) -> Result<ParsedFirstLine, Vec<Diagnostic>> {
+    parse_header(src).map(|header| header.map_or(ParsedFirstLine::Empty, ParsedFirstLine::from))
+}
+
+/// Like [`parse_first_line`], but always returns a best-effort parse; see
+/// [`parse_header_recovering`].
+pub fn parse_first_line_recovering(src: &str) -> (ParsedFirstLine, Vec<Diagnostic>) {
+    let (header, diagnostics) = parse_header_recovering(src);
+    (
+        header.map_or(ParsedFirstLine::Empty, ParsedFirstLine::from),
+        diagnostics,
+    )
+}
+
+/// One line parsed in isolation. Spans and diagnostics are relative to the
+/// start of the line (diagnostics report line 1); [`Assembler`] relocates them.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum LineParse {
+    Blank,
+    /// A whole-line `#` or `//` comment.
+    Comment,
+    /// A line not starting with `@`, without surrounding whitespace.
+    Text(String),
+    Directive(Directive),
+    /// A directive line that failed to parse, with its recovered directive.
+    Invalid(Directive, Vec<Diagnostic>),
+}
+
+/// Reusable per-line parser; building the chumsky parser once is what makes
+/// parsing many lines cheap.
+pub(crate) struct LineParser {
+    directive: chumsky::BoxedParser<'static, char, Directive, Simple<char>>,
+}
+
+impl LineParser {
+    pub(crate) fn new() -> Self {
+        LineParser {
+            directive: directive_parser().boxed(),
+        }
+    }
+
+    /// Parse `line`, which must not contain its terminator.
+    pub(crate) fn parse(&self, line: &str) -> LineParse {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            return LineParse::Blank;
+        }
+        if is_comment_line(trimmed) {
+            return LineParse::Comment;
+        }
+        if !trimmed.starts_with('@') {
+            return LineParse::Text(trimmed.to_string());
+        }
+        let start = line.len() - line.trim_start().len();
+        match parse_at(&self.directive, trimmed, start) {
+            Ok(directive) => LineParse::Directive(directive),
+            Err(errs) => LineParse::Invalid(
+                recover_directive(trimmed, start),
+                errs.into_iter().map(|e| to_diagnostic(line, e)).collect(),
+            ),
+        }
+    }
+}
+
+fn shift_span(span: &mut Span, by: isize) {
+    *span = span.start.saturating_add_signed(by)..span.end.saturating_add_signed(by);
+}
+
+impl Directive {
+    /// Move every span by `by` bytes.
+    pub(crate) fn shift(&mut self, by: isize) {
+        shift_span(&mut self.span, by);
+        shift_span(&mut self.name_span, by);
+        for arg in &mut self.args {
+            shift_span(&mut arg.span, by);
+        }
+        for span in &mut self.errors {
+            shift_span(span, by);
+        }
+        for pair in &mut self.pairs {
+            shift_span(&mut pair.key_span, by);
+            shift_span(&mut pair.value_span, by);
+        }
+    }
+
+    /// The pair for `key`. When the key repeats, the last pair wins.
+    pub fn get(&self, key: &str) -> Option<&Pair> {
+        self.pairs.iter().rev().find(|p| p.key == key)
+    }
+
+    /// Every pair for `key`, in source order.
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a Pair> + 'a {
+        self.pairs.iter().filter(move |p| p.key == key)
+    }
+
+    /// Whether the value at `span` is a placeholder for a missing or broken
+    /// one.
+    pub fn is_recovered(&self, span: &Span) -> bool {
+        self.errors.contains(span)
+    }
+}
+
+impl BodyNode {
+    /// Move every span by `by` bytes.
+    pub(crate) fn shift(&mut self, by: isize) {
+        shift_span(&mut self.span, by);
+        if let BodyKind::Directive(directive) = &mut self.kind {
+            directive.shift(by);
+        }
+    }
+}
+
+/// The part of a document a line belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Section {
+    /// Blank lines before the header block.
+    Preamble,
+    Header,
+    Body,
+}
+
+/// Builds a [`Document`] from per-line parses, fed in line order.
+pub(crate) struct Assembler {
+    pub(crate) doc: Document,
+    pub(crate) diagnostics: Vec<Diagnostic>,
+    /// For each pushed line, the index of its node in `doc.body` (`None` for
+    /// header lines, leading blank lines and lines that produced no node).
+    pub(crate) body_index: Vec<Option<usize>>,
+    /// The section the next line starts in.
+    pub(crate) section: Section,
+}
+
+impl Assembler {
+    pub(crate) fn new(section: Section) -> Self {
+        Assembler {
+            doc: Document::default(),
+            diagnostics: Vec::new(),
+            body_index: Vec::new(),
+            section,
         }
     }
+
+    /// Add line number `line_no` (0-based) starting at byte `offset`.
+    pub(crate) fn push(&mut self, line_no: usize, offset: usize, line: &str, parse: &LineParse) {
+        let in_body = self.section == Section::Body;
+        let (kind, section) = match parse {
+            LineParse::Blank if self.section == Section::Preamble => (None, Section::Preamble),
+            LineParse::Blank => (Some(BodyKind::Blank), Section::Body),
+            // Comments are not part of the document and never change the section.
+            LineParse::Comment => (None, self.section),
+            LineParse::Text(text) if self.section == Section::Preamble => {
+                let start = line.len() - line.trim_start().len();
+                self.diagnostics.push(
+                    missing_header(line, start..start + text.len()).relocate(offset, line_no + 1),
+                );
+                (None, Section::Body)
+            }
+            LineParse::Text(text) => (Some(BodyKind::Text(text.clone())), Section::Body),
+            LineParse::Directive(directive) | LineParse::Invalid(directive, _) => {
+                if let LineParse::Invalid(_, errs) = parse {
+                    self.diagnostics
+                        .extend(errs.iter().map(|d| d.clone().relocate(offset, line_no + 1)));
+                }
+                let mut directive = directive.clone();
+                directive.shift(offset as isize);
+                if in_body {
+                    (Some(BodyKind::Directive(directive)), Section::Body)
+                } else {
+                    self.doc.headers.push(directive);
+                    (None, Section::Header)
+                }
+            }
+        };
+        self.section = section;
+        self.body_index.push(kind.map(|kind| {
+            self.doc.body.push(BodyNode {
+                kind,
+                span: offset..offset + line.len(),
+            });
+            self.doc.body.len() - 1
+        }));
+    }
+}
+
+/// Parse every line of `src`, returning the document and all diagnostics.
+/// Broken directive lines are kept as recovered directives (see
+/// [`Directive::errors`]), so the document is usable even when there are
+/// diagnostics.
+pub fn parse_document_recovering(src: &str) -> (Document, Vec<Diagnostic>) {
+    let parser = LineParser::new();
+    let mut assembler = Assembler::new(Section::Preamble);
+    for (line_no, (offset, line)) in lines_with_offsets(src).enumerate() {
+        assembler.push(line_no, offset, line, &parser.parse(line));
+    }
+    (assembler.doc, assembler.diagnostics)
+}
+
+/// Parse the whole document: the leading directive lines form the header
+/// block, every following line becomes a [`BodyNode`]. Spans are byte offsets
+/// into `src`.
+///
+/// Every line is parsed, so the error case carries the diagnostics of all of them.
+pub fn parse_document(src: &str) -> Result<Document, Vec<Diagnostic>> {
+    let (doc, diagnostics) = parse_document_recovering(src);
+    if diagnostics.is_empty() {
+        Ok(doc)
+    } else {
+        Err(diagnostics)
+    }
 }
 
 // Highlighting --------------------------------------------------------------
 
-#[derive(Debug, Clone)]
-struct TokenSpan {
-    kind: &'static str,
-    start: usize,
-    end: usize,
-    text: String,
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct TokenSpan {
+    pub(crate) kind: &'static str,
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    pub(crate) text: String,
+    /// Whether the token is part of a directive's positional argument.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) positional: bool,
 }
 
 fn lexer() -> impl Parser<char, Vec<TokenSpan>, Error = Simple<char>> {
@@ -69,18 +737,25 @@ fn lexer() -> impl Parser<char, Vec<TokenSpan>, Error = Simple<char>> {
         start: span.start,
         end: span.end,
         text: "@".into(),
+        positional: false,
     });
     let ident = text::ident().map_with_span(|s: String, span: std::ops::Range<usize>| TokenSpan {
-        kind: "Ident",
+        kind: if s == "true" || s == "false" {
+            "Bool"
+        } else {
+            "Ident"
+        },
         start: span.start,
         end: span.end,
         text: s,
+        positional: false,
     });
     let eq = just('=').map_with_span(|_, span: std::ops::Range<usize>| TokenSpan {
         kind: "Equals",
         start: span.start,
         end: span.end,
         text: "=".into(),
+        positional: false,
     });
     let ws = one_of(" \t")
         .repeated()
@@ -91,57 +766,188 @@ fn lexer() -> impl Parser<char, Vec<TokenSpan>, Error = Simple<char>> {
             start: span.start,
             end: span.end,
             text: s,
+            positional: false,
         });
-    let value = none_of([' ', '\t', '\n', '@', '='])
+    let string = |quote: char| {
+        just(quote)
+            .chain(
+                just('\\')
+                    .chain(any())
+                    .or(none_of([quote, '\\']).map(|c| vec![c]))
+                    .repeated()
+                    .flatten(),
+            )
+            .chain::<char, _, _>(just(quote).or_not())
+            .collect::<String>()
+    };
+    let string = string('"')
+        .or(string('\''))
+        .map_with_span(|s, span: std::ops::Range<usize>| TokenSpan {
+            kind: "String",
+            start: span.start,
+            end: span.end,
+            text: s,
+            positional: false,
+        });
+    let punct = one_of("[]{},").map_with_span(|c, span: std::ops::Range<usize>| TokenSpan {
+        kind: match c {
+            '[' => "LBracket",
+            ']' => "RBracket",
+            '{' => "LBrace",
+            '}' => "RBrace",
+            _ => "Comma",
+        },
+        start: span.start,
+        end: span.end,
+        text: c.to_string(),
+        positional: false,
+    });
+    let interpolation = just('$')
+        .ignore_then(text::ident().delimited_by(just('{'), just('}')))
+        .map(|name: String| format!("${{{name}}}").chars().collect::<Vec<char>>());
+    let value = interpolation
+        .or(none_of([' ', '\t', '\n', '@', '=', '[', ']', '{', '}', ',']).map(|c| vec![c]))
         .repeated()
         .at_least(1)
+        .flatten()
         .collect::<String>()
         .map_with_span(|s, span: std::ops::Range<usize>| TokenSpan {
-            kind: "Value",
+            kind: match Value::from_bare(&s) {
+                Value::Int(_) | Value::Float(_) => "Number",
+                _ => "Value",
+            },
             start: span.start,
             end: span.end,
             text: s,
+            positional: false,
         });
-    choice((at, eq, ws, ident, value))
-        .repeated()
+    // Comments may only start the line or follow whitespace.
+    let comment = comment().map_with_span(|s, span: std::ops::Range<usize>| TokenSpan {
+        kind: "Comment",
+        start: span.start,
+        end: span.end,
+        text: s,
+        positional: false,
+    });
+    let ws = ws
+        .then(comment.clone().or_not())
+        .map(|(ws, comment)| std::iter::once(ws).chain(comment).collect::<Vec<_>>());
+    // Only reached by input no other token accepts, such as a stray `\n`.
+    let error = any().map_with_span(|c: char, span: std::ops::Range<usize>| TokenSpan {
+        kind: "Error",
+        start: span.start,
+        end: span.end,
+        text: c.to_string(),
+        positional: false,
+    });
+    let token = choice((at, eq, string, punct, ident, value, error)).map(|t| vec![t]);
+    comment
+        .or_not()
+        .then(ws.or(token).repeated().flatten())
+        .map(|(comment, rest)| comment.into_iter().chain(rest).collect())
         .then_ignore(end())
 }
 
-pub fn highlight_first_line_json(src: &str) -> String {
-    let mut first_non_empty = None;
-    let mut offset_base = 0usize;
-    for line in src.lines() {
-        let trimmed_line = line.trim_end_matches(['\r']);
-        if !trimmed_line.trim().is_empty() {
-            first_non_empty = Some((trimmed_line, offset_base));
-            break;
+/// Tokens of a single line, with byte spans. The lexer accepts any input, so
+/// the token texts always concatenate back to `line`.
+pub(crate) fn lex_line(line: &str) -> Vec<TokenSpan> {
+    let mut tokens = lexer().parse(line).unwrap_or_default();
+    // The lexer runs over chars, so its spans count chars.
+    if !line.is_ascii() {
+        let index = LineIndex::new(line);
+        let to_byte = |offset| {
+            index
+                .convert(offset, Encoding::Char, Encoding::Utf8)
+                .expect("lexer span within the line")
+        };
+        for token in &mut tokens {
+            token.start = to_byte(token.start);
+            token.end = to_byte(token.end);
         }
-        offset_base += line.len() + 1; // include newline
     }
-    let Some((line, base)) = first_non_empty else {
-        return "[]".into();
-    };
-    match lexer().parse(line) {
-        Ok(tokens) => {
-            let mut out = String::from("[");
-            for (i, t) in tokens.into_iter().enumerate() {
-                if i > 0 {
-                    out.push(',');
-                }
-                let escaped = t.text.replace('"', "\\\"");
-                out.push_str(&format!(
-                    "{{\"kind\":\"{}\",\"start\":{},\"end\":{},\"text\":\"{}\"}}",
-                    t.kind,
-                    base + t.start,
-                    base + t.end,
-                    escaped
-                ));
+    tokens
+}
+
+/// Split the `${name}` references out of string and value tokens into
+/// `Interpolation` tokens.
+fn split_interpolations(tokens: Vec<TokenSpan>) -> Vec<TokenSpan> {
+    let mut out = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        if !matches!(token.kind, "String" | "Value") {
+            out.push(token);
+            continue;
+        }
+        let mut piece = |kind, range: Span| {
+            if !range.is_empty() {
+                out.push(TokenSpan {
+                    kind,
+                    start: token.start + range.start,
+                    end: token.start + range.end,
+                    text: token.text[range].to_string(),
+                    positional: token.positional,
+                });
             }
-            out.push(']');
-            out
+        };
+        let mut last = 0;
+        for (span, _) in interpolations(&token.text) {
+            piece(token.kind, last..span.start);
+            last = span.end;
+            piece("Interpolation", span);
         }
-        Err(_) => "[]".into(),
+        piece(token.kind, last..token.text.len());
+    }
+    out
+}
+
+/// Lexer tokens of `line`, with byte spans into it: `${name}` references
+/// inside values are separate `Interpolation` tokens, the tokens of a
+/// directive's positional arguments are flagged and, on a broken directive
+/// line, the tokens inside the regions recovery could not place are `Error`
+/// tokens.
+pub(crate) fn highlight_line(line: &str) -> Vec<TokenSpan> {
+    let mut tokens = lex_line(line);
+    // The syntax tree decides what is an argument, as it does for `args`.
+    if let Some(directive) = Root::parse(line).header() {
+        for arg in directive.args() {
+            let range = arg.syntax().text_range();
+            let (start, end) = (usize::from(range.start()), usize::from(range.end()));
+            for token in &mut tokens {
+                token.positional |= start <= token.start && token.end <= end;
+            }
+        }
+    }
+    let (start, trimmed) = (line.len() - line.trim_start().len(), line.trim());
+    if trimmed.starts_with('@') && parse_at(&directive_parser(), trimmed, start).is_err() {
+        let errors = recover_directive(trimmed, start).errors;
+        for token in &mut tokens {
+            if errors
+                .iter()
+                .any(|e| e.start <= token.start && token.end <= e.end && !e.is_empty())
+            {
+                token.kind = "Error";
+            }
+        }
+    }
+    split_interpolations(tokens)
+}
+
+/// Lexer tokens of the first non-empty line as a JSON array of
+/// `{kind, start, end, text}` objects, with `start` and `end` in UTF-16 code
+/// units from the start of `src` as the DOM counts them. `${name}` references
+/// inside values are separate `Interpolation` tokens. On a broken directive
+/// line the tokens inside the regions recovery could not place are `Error`
+/// tokens. See [`crate::semantic`] for the whole document.
+pub fn highlight_first_line_json(src: &str) -> String {
+    let Some((offset, line)) = lines_with_offsets(src).find(|(_, l)| !l.trim().is_empty()) else {
+        return "[]".into();
+    };
+    let index = LineIndex::new(src);
+    let mut tokens = highlight_line(line);
+    for token in &mut tokens {
+        let span = index.span(offset + token.start..offset + token.end, Encoding::Utf16);
+        (token.start, token.end) = (span.start, span.end);
     }
+    serde_json::to_string(&tokens).expect("tokens serialize to JSON")
 }
 
 // ---------------- Tests (native only) -----------------
@@ -166,4 +972,263 @@ mod tests {
         show("missing_at", "option key=value\n");
         show("missing_value", "@option key=\n");
     }
+
+    #[test]
+    fn document_body() {
+        let src = "\n@option count=2\nWhat is 2+2?\n\n@note text=hi\r\n";
+        let doc = parse_document(src).unwrap();
+        let header = doc.header().unwrap();
+        assert_eq!(header.name, "option");
+        assert_eq!(header.pairs[0].value, Value::Int(2));
+        assert_eq!(&src[header.pairs[0].value_span.clone()], "2");
+        let kinds: Vec<_> = doc.body.iter().map(|n| &n.kind).collect();
+        assert!(matches!(kinds[0], BodyKind::Text(t) if t == "What is 2+2?"));
+        assert_eq!(kinds[1], &BodyKind::Blank);
+        assert!(matches!(kinds[2], BodyKind::Directive(d) if d.name == "note"));
+        assert_eq!(&src[doc.body[2].span.clone()], "@note text=hi");
+
+        let doc =
+            parse_document("@option\n@meta author=ana\n@scoring points=2\n\n@note\n").unwrap();
+        let names: Vec<_> = doc.headers.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, ["option", "meta", "scoring"]);
+        assert_eq!(doc.body[0].kind, BodyKind::Blank);
+        assert!(matches!(&doc.body[1].kind, BodyKind::Directive(d) if d.name == "note"));
+
+        let errs = parse_document("@option\n@bad key=\n@x =1\n").unwrap_err();
+        assert_eq!(errs.len(), 2);
+        assert_eq!(errs[0].code, codes::MISSING_VALUE);
+        assert_eq!(
+            (errs[0].line, errs[0].column, errs[0].span.clone()),
+            (2, 6, 13..17)
+        );
+        assert_eq!(errs[1].line, 3);
+    }
+
+    #[test]
+    fn diagnostic_render() {
+        let src = "@option count=\n";
+        let errs = parse_first_line(src).unwrap_err();
+        let rendered = errs[0].render(src, "q.dsl");
+        assert!(rendered.starts_with("error[E0003]: missing value for `count`\n --> q.dsl:1:9\n"));
+        assert!(rendered.contains("1 | @option count=\n  |         ^^^^^^ expected value\n"));
+    }
+
+    #[test]
+    fn quoted_values() {
+        let src = r#"@option title="Pick the \"best\" a=b @x" alt='it\'s' sym="\u{263A}\\\n""#;
+        let Ok(ParsedFirstLine::Directive { pairs, .. }) = parse_first_line(src) else {
+            panic!("expected directive");
+        };
+        assert_eq!(pairs[0].1, "Pick the \"best\" a=b @x".into());
+        assert_eq!(pairs[1].1, "it's".into());
+        assert_eq!(pairs[2].1, "\u{263A}\\\n".into());
+        let errs = parse_first_line(r#"@option title="open"#).unwrap_err();
+        assert_eq!(errs[0].expected, ["closing quote"]);
+        assert!(parse_first_line(r#"@option t="\q""#).is_err());
+        assert!(parse_first_line(r#"@option t="\u{D800}""#).is_err());
+        let json = highlight_first_line_json(r#"@option t="a \"b\"" u=1"#);
+        assert!(json.contains(r#"{"kind":"String","start":10,"end":19,"text":"\"a \\\"b\\\"\""}"#));
+        let json = highlight_first_line_json("\n@x t=\"a\\\\b\tc\u{1}\"");
+        let tokens: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        assert_eq!(tokens[5]["text"], "\"a\\\\b\tc\u{1}\"");
+        assert_eq!(tokens[0]["start"], 1);
+        let json = highlight_first_line_json("\r\n@option prompt=\"日本語😀\" points=2");
+        let tokens: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        let spans: Vec<_> = tokens
+            .iter()
+            .map(|t| (t["start"].clone(), t["end"].clone()))
+            .collect();
+        assert_eq!(spans[5], (17.into(), 24.into()));
+        assert_eq!(spans[7], (25.into(), 31.into()));
+    }
+
+    #[test]
+    fn comments() {
+        let src = "# reviewer: check wording\n@option prompt=#1 points=2  // was 3\n// body note\nQ #2?\n";
+        let doc = parse_document(src).unwrap();
+        let header = doc.header().unwrap();
+        assert_eq!(header.pairs[0].value, "#1".into());
+        assert_eq!(&src[header.span.clone()], "@option prompt=#1 points=2");
+        assert!(
+            matches!(&doc.body[..], [BodyNode { kind: BodyKind::Text(t), .. }] if t == "Q #2?")
+        );
+        assert!(parse_first_line("@option x=1#c").is_ok());
+        assert_eq!(
+            parse_document("@option x= # c").unwrap_err()[0].code,
+            codes::MISSING_VALUE
+        );
+
+        let kinds = |line: &str| -> Vec<&str> { lex_line(line).iter().map(|t| t.kind).collect() };
+        assert_eq!(kinds("# a=b"), ["Comment"]);
+        assert_eq!(
+            kinds("@x y=#1 #c"),
+            [
+                "At", "Ident", "Ws", "Ident", "Equals", "Value", "Ws", "Comment"
+            ]
+        );
+    }
+
+    #[test]
+    fn typed_values() {
+        let src = "@option count=10 ratio=-0.5 shuffle=true path=/a,b id='7' \
+                   answers=[a, 2, [false]] grading={mode=partial, weights=[1, 2.5],}";
+        let Ok(ParsedFirstLine::Directive { pairs, .. }) = parse_first_line(src) else {
+            panic!("expected directive");
+        };
+        let values: Vec<_> = pairs.into_iter().map(|(_, v)| v).collect();
+        assert_eq!(
+            values,
+            [
+                Value::Int(10),
+                Value::Float(-0.5),
+                Value::Bool(true),
+                "/a,b".into(),
+                "7".into(),
+                Value::List(vec!["a".into(), 2.into(), Value::List(vec![false.into()])]),
+                Value::Map(vec![
+                    ("mode".into(), "partial".into()),
+                    ("weights".into(), Value::List(vec![1.into(), 2.5.into()])),
+                ]),
+            ]
+        );
+        for value in &values {
+            let line = format!("@x v={value}");
+            let Ok(ParsedFirstLine::Directive { pairs, .. }) = parse_first_line(&line) else {
+                panic!("{line} does not parse");
+            };
+            assert_eq!(&pairs[0].1, value);
+        }
+        let errs = parse_first_line("@option answers=[a, b").unwrap_err();
+        assert!(errs[0].expected.contains(&"`]`".to_string()));
+        let json = highlight_first_line_json("@x n=[1, true]");
+        assert!(json.contains(r#""kind":"LBracket""#) && json.contains(r#""kind":"Number""#));
+        assert!(json.contains(r#""kind":"Bool""#) && json.contains(r#""kind":"Comma""#));
+    }
+
+    #[test]
+    fn interpolations() {
+        let src = "@x a=[${b}, c] m={k=${v}} s=\"${n}!\" w=x${y}";
+        let Ok(ParsedFirstLine::Directive { pairs, .. }) = parse_first_line(src) else {
+            panic!("expected directive");
+        };
+        assert_eq!(pairs[0].1, Value::List(vec!["${b}".into(), "c".into()]));
+        assert_eq!(pairs[1].1, Value::Map(vec![("k".into(), "${v}".into())]));
+        for (_, value) in &pairs {
+            assert_eq!(parse_value(&value.to_string()).as_ref(), Some(value));
+        }
+        assert_eq!(pairs[3].1.to_string(), "x${y}");
+
+        let json = highlight_first_line_json(src);
+        let tokens: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        let pieces: Vec<_> = tokens
+            .iter()
+            .filter(|t| matches!(t["kind"].as_str(), Some("Interpolation" | "String")))
+            .map(|t| (t["kind"].as_str().unwrap(), t["text"].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            pieces,
+            [
+                ("Interpolation", "${b}"),
+                ("Interpolation", "${v}"),
+                ("String", "\""),
+                ("Interpolation", "${n}"),
+                ("String", "!\""),
+                ("Interpolation", "${y}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn positional_args() {
+        let src = "@option \"What is 2+2?\" points=1 [a, b] /x  # c";
+        let Ok(Some(header)) = parse_header(src) else {
+            panic!("expected directive");
+        };
+        let args: Vec<_> = header
+            .args
+            .iter()
+            .map(|a| (a.value.clone(), &src[a.span.clone()]))
+            .collect();
+        assert_eq!(
+            args,
+            [
+                ("What is 2+2?".into(), "\"What is 2+2?\""),
+                (Value::List(vec!["a".into(), "b".into()]), "[a, b]"),
+                ("/x".into(), "/x"),
+            ]
+        );
+        assert_eq!(header.pairs[0].key, "points");
+        for bad in ["@x 1=2", "@x \"a\"b", "@x =1"] {
+            assert!(parse_header(bad).is_err(), "{bad}");
+        }
+
+        let json = highlight_first_line_json("@option 'Q' n=1 [x]");
+        let tokens: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        let positional: Vec<_> = tokens
+            .iter()
+            .filter(|t| t["positional"] == true)
+            .map(|t| t["text"].as_str().unwrap())
+            .collect();
+        assert_eq!(positional, ["'Q'", "[", "x", "]"]);
+        let positional: Vec<_> = highlight_line("  @x k = [a, b] w  # c")
+            .into_iter()
+            .filter(|t| t.positional)
+            .map(|t| t.text)
+            .collect();
+        assert_eq!(positional, ["w"]);
+    }
+
+    #[test]
+    fn recovers_broken_lines() {
+        let src = "@option count=2 title=\"open";
+        let (header, errs) = parse_header_recovering(src);
+        let header = header.unwrap();
+        assert_eq!(errs[0].expected, ["closing quote"]);
+        assert_eq!(header.name, "option");
+        assert_eq!(header.pairs[0].value, Value::Int(2));
+        assert!(!header.is_recovered(&header.pairs[0].value_span));
+        assert!(header.is_recovered(&header.pairs[1].value_span));
+        let errors: Vec<_> = header.errors.iter().map(|e| &src[e.clone()]).collect();
+        assert_eq!(errors, ["\"open"]);
+
+        let (header, _) = parse_header_recovering("\n  @ count=2 1=3 key x=[a, 1]");
+        let header = header.unwrap();
+        assert_eq!((header.name.as_str(), header.name_span.clone()), ("", 4..4));
+        assert_eq!(header.pairs[0].value, Value::Int(2));
+        assert_eq!(
+            header.pairs[1].value,
+            Value::List(vec!["a".into(), 1.into()])
+        );
+        assert_eq!(header.errors, [4..4, 13..16]);
+        assert_eq!(
+            header.args[0],
+            Arg {
+                value: "key".into(),
+                span: 17..20
+            }
+        );
+        let (first, errs) = parse_first_line_recovering("@option key=");
+        assert_eq!(errs[0].code, codes::MISSING_VALUE);
+        assert!(matches!(first, ParsedFirstLine::Directive { name, .. } if name == "option"));
+
+        // Broken lines keep their place in the document.
+        let src = "@option count=\n@meta x=[1\nQ?\n@x =\n";
+        let (doc, errs) = parse_document_recovering(src);
+        assert_eq!(errs.len(), 3);
+        assert_eq!(doc.headers.len(), 2);
+        let BodyKind::Directive(d) = &doc.body[1].kind else {
+            panic!("expected directive");
+        };
+        assert_eq!((&src[d.errors[0].clone()], d.errors.len()), ("=", 1));
+
+        let json = highlight_first_line_json("@option t=\"ab x=1 $");
+        let tokens: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        let errors: Vec<_> = tokens
+            .iter()
+            .filter(|t| t["kind"] == "Error")
+            .map(|t| t["text"].as_str().unwrap())
+            .collect();
+        assert_eq!(errors, ["\"ab x=1 $"]);
+        assert_eq!(lex_line("a\nb")[1].kind, "Error");
+    }
 }
@@ -0,0 +1,682 @@
+//! Question bodies.
+//!
+//! The header directive names the question kind; the text lines below it hold
+//! the prompt and the answers:
+//!
+//! ```text
+//! @option
+//! Ibu kota Jepang?
+//! * Tokyo
+//! - Kyoto
+//! ```
+//!
+//! `option` and `multi_option` list choices, `*` for correct and `-` for
+//! wrong ones. `matching_pair` lists `left => right` pairs, plus `~` lines for
+//! extra right-hand items that match nothing. Text above the first answer is
+//! the prompt; without it the header's prompt, `@option "Why?"` or
+//! `prompt=`, is used.
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::mem;
+
+use crate::diagnostic::{Diagnostic, Locate, codes};
+use crate::eval::evaluate;
+use crate::import::Import;
+use crate::line_index::LineIndex;
+use crate::parser::{BodyKind, Document, Span, parse_document};
+use crate::schema::{ValidateOptions, bind_args, main_directive, validate_directives};
+use crate::value::Value;
+
+/// One answer of an `option` or `multi_option` question.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Choice {
+    pub text: String,
+    pub correct: bool,
+    /// Span of the text, without the marker.
+    pub span: Span,
+}
+
+/// One `left => right` line of a `matching_pair` question.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchPair {
+    pub left: String,
+    pub right: String,
+    pub left_span: Span,
+    pub right_span: Span,
+}
+
+/// A `~` right-hand item that matches no left-hand item.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distractor {
+    pub text: String,
+    pub span: Span,
+}
+
+/// The answers of a question, by kind.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Answers {
+    Option(Vec<Choice>),
+    MultiOption(Vec<Choice>),
+    MatchingPair {
+        pairs: Vec<MatchPair>,
+        distractors: Vec<Distractor>,
+    },
+}
+
+/// A question read from a document's header and body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Question {
+    pub prompt: String,
+    pub answers: Answers,
+}
+
+impl Question {
+    /// The question kind, as the header directive names it.
+    pub fn kind(&self) -> &'static str {
+        match self.answers {
+            Answers::Option(_) => "option",
+            Answers::MultiOption(_) => "multi_option",
+            Answers::MatchingPair { .. } => "matching_pair",
+        }
+    }
+
+    pub fn import(&self) -> Import {
+        let prompt = self.prompt.clone();
+        match self.answers {
+            Answers::Option(_) => Import::Option(prompt),
+            Answers::MultiOption(_) => Import::MultiOption(prompt),
+            Answers::MatchingPair { .. } => Import::MatchingPair(prompt),
+        }
+    }
+}
+
+/// Parse, evaluate and validate `src`, then read its question. Any error, in
+/// the header or the body, fails the parse.
+pub fn parse_question(src: &str) -> Result<Question, Vec<Diagnostic>> {
+    let (doc, mut diagnostics) = evaluate(src, &parse_document(src)?);
+    diagnostics.extend(validate_directives(src, &doc, &ValidateOptions::default()));
+    let (question, body) = read_question(src, &doc);
+    diagnostics.extend(body);
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        return Err(diagnostics);
+    }
+    match question {
+        Some(question) => Ok(question),
+        None => {
+            let span = main_directive(&doc.headers).map_or(0..0, |h| h.name_span.clone());
+            Err(vec![
+                Diagnostic::error(
+                    src,
+                    codes::NOT_A_QUESTION,
+                    span,
+                    "expected a question directive",
+                )
+                .with_expected(
+                    ["`@option`", "`@multi_option`", "`@matching_pair`"]
+                        .map(String::from)
+                        .to_vec(),
+                ),
+            ])
+        }
+    }
+}
+
+/// Read the question of a parsed document together with the body's
+/// diagnostics. `None` if the header, after any `@define` and `@include`
+/// lines, does not name a question kind.
+pub fn read_question(src: &str, doc: &Document) -> (Option<Question>, Vec<Diagnostic>) {
+    let Some(header) = main_directive(&doc.headers).map(bind_args) else {
+        return (None, Vec::new());
+    };
+    let mut reader = Reader {
+        index: LineIndex::new(src),
+        kind: header.name.as_str(),
+        prompt: Vec::new(),
+        answered: false,
+        choices: Vec::new(),
+        pairs: Vec::new(),
+        distractors: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+    if !matches!(reader.kind, "option" | "multi_option" | "matching_pair") {
+        return (None, Vec::new());
+    }
+    for node in &doc.body {
+        if let BodyKind::Text(text) = &node.kind {
+            let line = &src[node.span.clone()];
+            let start = node.span.start + line.len() - line.trim_start().len();
+            reader.line(line.trim(), text, start);
+        }
+    }
+
+    let header_prompt = header.get("prompt").and_then(|p| match &p.value {
+        Value::String(s) => Some(s.clone()),
+        _ => None,
+    });
+    let prompt = if reader.prompt.is_empty() {
+        header_prompt.unwrap_or_default()
+    } else {
+        reader.prompt.join("\n")
+    };
+    if prompt.is_empty() {
+        reader.error(
+            codes::MISSING_PROMPT,
+            header.name_span.clone(),
+            format!("{} question has no prompt", reader.kind),
+            "write the question above the answers, or set `prompt=`",
+        );
+    }
+    let count = header.get("count");
+    reader.finish(
+        header.name_span.clone(),
+        count.map(|p| (&p.value, p.value_span.clone())),
+    );
+
+    let Reader {
+        kind,
+        choices,
+        pairs,
+        distractors,
+        diagnostics,
+        ..
+    } = reader;
+    let answers = match kind {
+        "option" => Answers::Option(choices),
+        "multi_option" => Answers::MultiOption(choices),
+        _ => Answers::MatchingPair { pairs, distractors },
+    };
+    (Some(Question { prompt, answers }), diagnostics)
+}
+
+/// Check the body of a parsed document against its question kind.
+pub fn validate_question(src: &str, doc: &Document) -> Vec<Diagnostic> {
+    read_question(src, doc).1
+}
+
+/// The parts of a trimmed body line, as byte ranges into it.
+enum Shape {
+    /// A `*` (correct) or `-` choice and its text.
+    Choice { correct: bool, answer: Span },
+    /// A `~` distractor's text.
+    Distractor(Span),
+    /// `left => right`, with the offset of the `=>`.
+    Pair {
+        left: Span,
+        arrow: usize,
+        right: Span,
+    },
+    /// Prompt text, or text out of place.
+    Text,
+}
+
+impl Shape {
+    /// Split `line`; only matching pairs read `=>` as a pair, other kinds
+    /// read it as ordinary prompt text.
+    fn of(line: &str, matching: bool) -> Shape {
+        // The answer text after a one-character marker.
+        let after_marker = |marker: char| {
+            let rest = line.strip_prefix(marker)?;
+            if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+                return None;
+            }
+            Some(line.len() - rest.trim_start().len()..line.len())
+        };
+        if let Some(answer) = after_marker('*') {
+            Shape::Choice {
+                correct: true,
+                answer,
+            }
+        } else if let Some(answer) = after_marker('-') {
+            Shape::Choice {
+                correct: false,
+                answer,
+            }
+        } else if let Some(answer) = after_marker('~') {
+            Shape::Distractor(answer)
+        } else if let Some(arrow) = line.find("=>").filter(|_| matching) {
+            Shape::Pair {
+                left: 0..line[..arrow].trim_end().len(),
+                arrow,
+                right: line.len() - line[arrow + 2..].trim_start().len()..line.len(),
+            }
+        } else {
+            Shape::Text
+        }
+    }
+
+    /// The answer texts, in order.
+    fn parts(&self) -> Vec<Span> {
+        match self {
+            Shape::Choice { answer, .. } | Shape::Distractor(answer) => vec![answer.clone()],
+            Shape::Pair { left, right, .. } => vec![left.clone(), right.clone()],
+            Shape::Text => Vec::new(),
+        }
+    }
+}
+
+struct Reader<'a> {
+    index: LineIndex,
+    kind: &'a str,
+    prompt: Vec<String>,
+    /// Whether an answer line has been seen; prompt text must come before.
+    answered: bool,
+    choices: Vec<Choice>,
+    pairs: Vec<MatchPair>,
+    distractors: Vec<Distractor>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Reader<'_> {
+    fn error(&mut self, code: &'static str, span: Span, message: String, help: &str) {
+        self.diagnostics
+            .push(Diagnostic::error(&self.index, code, span, message).with_help(help));
+    }
+
+    /// Read one body line: `source` is the trimmed line as written, starting
+    /// at byte `start`, and `text` the same line with its variables resolved.
+    /// Spans point into the source; answers hold the resolved text.
+    fn line(&mut self, source: &str, text: &str, start: usize) {
+        let end = start + source.len();
+        let at = |range: &Span| start + range.start..start + range.end;
+        let matching = self.kind == "matching_pair";
+        let shape = Shape::of(source, matching);
+        let resolved = Shape::of(text, matching);
+        // A variable that adds or removes a marker leaves the source's parts.
+        let parts: Vec<&str> = if mem::discriminant(&shape) == mem::discriminant(&resolved) {
+            resolved.parts().iter().map(|r| &text[r.clone()]).collect()
+        } else {
+            shape.parts().iter().map(|r| &source[r.clone()]).collect()
+        };
+
+        match shape {
+            Shape::Choice { correct, answer } => {
+                self.answered = true;
+                if matching {
+                    self.unexpected(start..end, "choices");
+                } else if parts[0].is_empty() {
+                    self.empty(start..start + 1, "choice has no text");
+                } else {
+                    self.choices.push(Choice {
+                        text: parts[0].to_string(),
+                        correct,
+                        span: at(&answer),
+                    });
+                }
+            }
+            Shape::Distractor(answer) => {
+                self.answered = true;
+                if !matching {
+                    self.unexpected(start..end, "`~` distractors");
+                } else if parts[0].is_empty() {
+                    self.empty(start..start + 1, "distractor has no text");
+                } else {
+                    self.distractors.push(Distractor {
+                        text: parts[0].to_string(),
+                        span: at(&answer),
+                    });
+                }
+            }
+            Shape::Pair { left, arrow, right } => {
+                self.answered = true;
+                let arrow = start + arrow..start + arrow + 2;
+                if parts[0].is_empty() {
+                    self.empty(arrow, "pair has no left-hand side");
+                } else if parts[1].is_empty() {
+                    self.empty(arrow, "pair has no right-hand side");
+                } else {
+                    self.pairs.push(MatchPair {
+                        left: parts[0].to_string(),
+                        right: parts[1].to_string(),
+                        left_span: at(&left),
+                        right_span: at(&right),
+                    });
+                }
+            }
+            Shape::Text if self.answered => self.error(
+                codes::UNEXPECTED_LINE,
+                start..end,
+                "text after the answers".to_string(),
+                "move prompt text above the first answer",
+            ),
+            Shape::Text => self.prompt.push(text.to_string()),
+        }
+    }
+
+    fn unexpected(&mut self, span: Span, what: &str) {
+        let (belongs, instead) = if self.kind == "matching_pair" {
+            (
+                "option and multi_option",
+                "write `left => right` pairs, or `~ item` for distractors",
+            )
+        } else {
+            (
+                "matching_pair",
+                "mark choices with `*` (correct) or `-` (wrong)",
+            )
+        };
+        self.error(
+            codes::UNEXPECTED_LINE,
+            span,
+            format!("{what} belong in {belongs} questions"),
+            instead,
+        );
+    }
+
+    fn empty(&mut self, span: Span, message: &str) {
+        self.error(
+            codes::EMPTY_ANSWER,
+            span,
+            message.to_string(),
+            "write the answer after the marker",
+        );
+    }
+
+    /// The whole-question checks. `header` is the span of the header's name.
+    fn finish(&mut self, header: Span, count: Option<(&Value, Span)>) {
+        let line_of = |index: &LineIndex, span: &Span| index.line_column(span.start).0;
+        let mut duplicates = Vec::new();
+        if self.kind == "matching_pair" {
+            let mut lefts: HashMap<&str, &Span> = HashMap::new();
+            let mut rights: HashMap<&str, &Span> = HashMap::new();
+            for pair in &self.pairs {
+                if let Some(prev) = earlier(&mut lefts, &pair.left, &pair.left_span) {
+                    duplicates.push((
+                        pair.left_span.clone(),
+                        "duplicate left-hand side in matching pair",
+                        line_of(&self.index, prev),
+                    ));
+                }
+            }
+            let rights_iter = self
+                .pairs
+                .iter()
+                .map(|p| (&p.right, &p.right_span))
+                .chain(self.distractors.iter().map(|d| (&d.text, &d.span)));
+            for (text, span) in rights_iter {
+                if let Some(prev) = earlier(&mut rights, text, span) {
+                    duplicates.push((
+                        span.clone(),
+                        "duplicate right-hand side in matching pair",
+                        line_of(&self.index, prev),
+                    ));
+                }
+            }
+        } else {
+            let mut texts: HashMap<&str, &Span> = HashMap::new();
+            for choice in &self.choices {
+                if let Some(prev) = earlier(&mut texts, &choice.text, &choice.span) {
+                    duplicates.push((
+                        choice.span.clone(),
+                        "duplicate choice",
+                        line_of(&self.index, prev),
+                    ));
+                }
+            }
+        }
+        for (span, message, line) in duplicates {
+            self.error(
+                codes::DUPLICATE_ANSWER,
+                span,
+                message.to_string(),
+                &format!("first listed on line {line}"),
+            );
+        }
+
+        let kind = self.kind;
+        match kind {
+            "matching_pair" => {
+                let wanted = match count {
+                    Some((Value::Int(n), span)) if *n > 0 => Some((*n as usize, span)),
+                    _ => None,
+                };
+                let have = self.pairs.len();
+                if have == 0 {
+                    self.error(
+                        codes::TOO_FEW_ANSWERS,
+                        header,
+                        "matching_pair question has no pairs".to_string(),
+                        "add `left => right` lines below the prompt",
+                    );
+                } else if let Some((wanted, span)) = wanted.filter(|(n, _)| *n > have) {
+                    self.error(
+                        codes::TOO_FEW_ANSWERS,
+                        span,
+                        format!("`count` is {wanted} but the question has {have} pairs"),
+                        "add pairs or lower `count`",
+                    );
+                }
+            }
+            _ => {
+                let correct: Vec<Span> = self
+                    .choices
+                    .iter()
+                    .filter(|c| c.correct)
+                    .map(|c| c.span.clone())
+                    .collect();
+                if self.choices.len() < 2 {
+                    self.error(
+                        codes::TOO_FEW_ANSWERS,
+                        header.clone(),
+                        format!("{kind} question needs at least two choices"),
+                        "add choices with `*` (correct) or `-` (wrong)",
+                    );
+                }
+                if correct.is_empty() && !self.choices.is_empty() {
+                    self.error(
+                        codes::NO_CORRECT_ANSWER,
+                        header,
+                        format!("{kind} question has no correct answer"),
+                        "mark the correct choice with `*`",
+                    );
+                } else if kind == "option" {
+                    for span in correct.into_iter().skip(1) {
+                        self.error(
+                            codes::MULTIPLE_CORRECT,
+                            span,
+                            "option question has more than one correct answer".to_string(),
+                            "use `@multi_option` for several correct answers",
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The span `text` was first seen at, or `None` (recording `span`) if this is
+/// the first time.
+fn earlier<'a>(
+    seen: &mut HashMap<&'a str, &'a Span>,
+    text: &'a str,
+    span: &'a Span,
+) -> Option<&'a Span> {
+    match seen.entry(text) {
+        Entry::Occupied(first) => Some(*first.get()),
+        Entry::Vacant(slot) => {
+            slot.insert(span);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes_of(src: &str) -> Vec<(&'static str, String, &str)> {
+        let doc = parse_document(src).unwrap();
+        validate_question(src, &doc)
+            .into_iter()
+            .map(|d| (d.code, d.message, &src[d.span]))
+            .collect()
+    }
+
+    #[test]
+    fn reads_each_kind() {
+        let q = parse_question("@option\nIbu kota\nJepang?\n\n* Tokyo\n-  Kyoto\n").unwrap();
+        assert_eq!(q.prompt, "Ibu kota\nJepang?");
+        let Answers::Option(choices) = &q.answers else {
+            panic!("{q:?}")
+        };
+        assert_eq!(
+            choices
+                .iter()
+                .map(|c| (c.text.as_str(), c.correct))
+                .collect::<Vec<_>>(),
+            [("Tokyo", true), ("Kyoto", false)]
+        );
+
+        let src = "@multi_option prompt=\"Prima?\"\n* 2\n* 3\n- 4\n";
+        let q = parse_question(src).unwrap();
+        assert_eq!((q.kind(), q.prompt.as_str()), ("multi_option", "Prima?"));
+        let q = parse_question("@option 'Ibu kota?' points=2\n* Tokyo\n- Kyoto\n").unwrap();
+        assert_eq!(q.prompt, "Ibu kota?");
+
+        let src = "@matching_pair count=2\nPasangkan\nJepang => Tokyo\n  Prancis=>Paris\n~ Roma\n";
+        let q = parse_question(src).unwrap();
+        let Answers::MatchingPair { pairs, distractors } = &q.answers else {
+            panic!("{q:?}")
+        };
+        assert_eq!(&src[pairs[1].left_span.clone()], "Prancis");
+        assert_eq!(&src[pairs[1].right_span.clone()], "Paris");
+        assert_eq!(distractors[0].text, "Roma");
+        assert!(matches!(q.import(), Import::MatchingPair(p) if p == "Pasangkan"));
+
+        // The question directive may follow `@define` and `@include`.
+        let q = parse_question("@define n=2\n@option points=${n}\nQ?\n* a\n- b\n").unwrap();
+        assert_eq!((q.kind(), q.prompt.as_str()), ("option", "Q?"));
+        assert_eq!(
+            parse_question("@define n=2\n@meta\n").unwrap_err()[0].code,
+            codes::NOT_A_QUESTION
+        );
+    }
+
+    #[test]
+    fn spans_of_interpolated_lines_point_into_the_source() {
+        let src = "@define a=x b='a much longer answer'\n@option\nQ?\n* ${a}\n- ${b}\n-  ${b}\n";
+        let (doc, _) = evaluate(src, &parse_document(src).unwrap());
+        let (question, diagnostics) = read_question(src, &doc);
+        let Answers::Option(choices) = question.unwrap().answers else {
+            panic!()
+        };
+        let read: Vec<_> = choices
+            .iter()
+            .map(|c| (c.text.as_str(), &src[c.span.clone()]))
+            .collect();
+        assert_eq!(
+            read,
+            [
+                ("x", "${a}"),
+                ("a much longer answer", "${b}"),
+                ("a much longer answer", "${b}"),
+            ]
+        );
+        let located: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.code, &src[d.span.clone()], d.line))
+            .collect();
+        assert_eq!(located, [(codes::DUPLICATE_ANSWER, "${b}", 6)]);
+
+        let src = "@define l=Jepang\n@matching_pair\nQ?\n${l} => Tokyo\n~ ${l}\n";
+        let (doc, _) = evaluate(src, &parse_document(src).unwrap());
+        let q = read_question(src, &doc).0.unwrap();
+        let Answers::MatchingPair { pairs, distractors } = &q.answers else {
+            panic!("{q:?}")
+        };
+        assert_eq!(pairs[0].left, "Jepang");
+        assert_eq!(&src[pairs[0].left_span.clone()], "${l}");
+        assert_eq!(&src[pairs[0].right_span.clone()], "Tokyo");
+        assert_eq!(&src[distractors[0].span.clone()], "${l}");
+    }
+
+    #[test]
+    fn markers_need_a_space_and_spans_survive_crlf_and_unicode() {
+        let src = "@option\r\n*Bold* claim?\r\n*\t日本\r\n-\r\n- x\r\n";
+        let doc = parse_document(src).unwrap();
+        let (question, diagnostics) = read_question(src, &doc);
+        let question = question.unwrap();
+        assert_eq!(question.prompt, "*Bold* claim?");
+        let Answers::Option(choices) = &question.answers else {
+            panic!("{question:?}")
+        };
+        assert_eq!(choices[0].text, "日本");
+        assert_eq!(&src[choices[0].span.clone()], "日本");
+        assert_eq!(&src[choices[1].span.clone()], "x");
+        let located: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.code, &src[d.span.clone()], d.line, d.column))
+            .collect();
+        assert_eq!(located, [(codes::EMPTY_ANSWER, "-", 4, 1)]);
+    }
+
+    #[test]
+    fn kind_specific_diagnostics() {
+        assert_eq!(
+            codes_of("@option\nQ?\n- a\n- b\n"),
+            [(
+                codes::NO_CORRECT_ANSWER,
+                "option question has no correct answer".to_string(),
+                "option"
+            )]
+        );
+        assert_eq!(
+            codes_of("@option\nQ?\n* a\n* b\n- a\nlate\n"),
+            [
+                (
+                    codes::UNEXPECTED_LINE,
+                    "text after the answers".to_string(),
+                    "late"
+                ),
+                (codes::DUPLICATE_ANSWER, "duplicate choice".to_string(), "a"),
+                (
+                    codes::MULTIPLE_CORRECT,
+                    "option question has more than one correct answer".to_string(),
+                    "b"
+                ),
+            ]
+        );
+        assert_eq!(
+            codes_of("@multi_option\n* x\na => b\n"),
+            [
+                (
+                    codes::UNEXPECTED_LINE,
+                    "text after the answers".to_string(),
+                    "a => b"
+                ),
+                (
+                    codes::MISSING_PROMPT,
+                    "multi_option question has no prompt".to_string(),
+                    "multi_option"
+                ),
+                (
+                    codes::TOO_FEW_ANSWERS,
+                    "multi_option question needs at least two choices".to_string(),
+                    "multi_option"
+                ),
+            ]
+        );
+        let src = "@matching_pair count=3\nQ?\na => 1\nb => 1\na =>\n~ 1\n* c\n";
+        let diags = codes_of(src);
+        assert_eq!(
+            diags.iter().map(|d| (d.0, d.2)).collect::<Vec<_>>(),
+            [
+                (codes::EMPTY_ANSWER, "=>"),
+                (codes::UNEXPECTED_LINE, "* c"),
+                (codes::DUPLICATE_ANSWER, "1"),
+                (codes::DUPLICATE_ANSWER, "1"),
+                (codes::TOO_FEW_ANSWERS, "3"),
+            ]
+        );
+        assert_eq!(diags[2].1, "duplicate right-hand side in matching pair");
+        // Only matching pairs read `=>` as a pair.
+        let q = parse_question("@option\nWhat does x => y mean?\n* a\n- b\n").unwrap();
+        assert_eq!(q.prompt, "What does x => y mean?");
+        // Not a question: nothing to check.
+        assert!(codes_of("@meta\n- a\n").is_empty());
+        assert_eq!(
+            parse_question("@meta\n").unwrap_err()[0].code,
+            codes::NOT_A_QUESTION
+        );
+    }
+}
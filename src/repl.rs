@@ -0,0 +1,404 @@
+//! Interactive REPL.
+//!
+//! A [`Session`] parses and validates each line as it is entered and prints
+//! the syntax tree and diagnostics. A line opening a question (`@option`,
+//! `@multi_option`, `@matching_pair`) starts a multi-line entry: the lines
+//! that follow are collected, each checked as it arrives, until an empty
+//! line ends the question, which is then validated and read as a whole.
+//! Lines starting with `:` are commands (see [`HELP`]). [`run`] drives a
+//! session from a line editor with history.
+
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+
+use crate::diagnostic::Diagnostic;
+use crate::eval::evaluate;
+use crate::parser::{BodyKind, Directive, Span, highlight_line, parse_document_recovering};
+use crate::question::read_question;
+use crate::schema::{SCHEMAS, schema_for, suggest, validate_directive, validate_document};
+use crate::value::Value;
+
+pub const HELP: &str = "\
+Enter a directive or text line to parse and check it. A question directive
+such as `@option \"Why?\"` starts a question: enter its body line by line and
+finish it with an empty line.
+
+:tokens [line]   show the lexer tokens of `line`, or of the last line entered
+:schema [name]   show a directive's parameters, or list the directives
+:help            show this help
+:quit            leave
+";
+
+const COMMANDS: &[&str] = &["tokens", "schema", "help", "quit"];
+
+/// Name a diagnostic's source is shown under.
+const ORIGIN: &str = "<repl>";
+
+#[derive(Debug, Default)]
+pub struct Session {
+    /// The lines of the question being entered, each with its terminator.
+    question: Option<String>,
+    /// The last line that was not a command.
+    last: String,
+    done: bool,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The prompt for the next line: a continuation prompt inside a
+    /// question.
+    pub fn prompt(&self) -> &'static str {
+        if self.question.is_some() {
+            ".. "
+        } else {
+            "q> "
+        }
+    }
+
+    /// Whether `:quit` was entered.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Handle one line of input, without its terminator, and return what to
+    /// print.
+    pub fn feed(&mut self, line: &str) -> String {
+        if let Some(command) = line.trim().strip_prefix(':') {
+            return self.command(command.trim());
+        }
+        if line.trim().is_empty() {
+            return match self.question.take() {
+                Some(src) => finish(&src),
+                None => String::new(),
+            };
+        }
+        self.last = line.to_string();
+        match &mut self.question {
+            Some(src) => {
+                let start = src.len();
+                src.push_str(line);
+                src.push('\n');
+                continue_question(src, start)
+            }
+            None => self.single(line),
+        }
+    }
+
+    /// Abandon the question being entered, if any.
+    pub fn cancel(&mut self) -> bool {
+        self.question.take().is_some()
+    }
+
+    fn single(&mut self, line: &str) -> String {
+        let (doc, diagnostics) = parse_document_recovering(line);
+        let mut out = String::new();
+        if let Some(directive) = doc.header() {
+            out.push_str(&show_directive(directive));
+            let mut diagnostics = diagnostics;
+            diagnostics.extend(validate_directive(line, directive));
+            out.push_str(&render(line, &diagnostics));
+            if schema_for(&directive.name).is_some_and(|s| s.first) {
+                self.question = Some(format!("{line}\n"));
+                out.push_str("(question started: enter its body, then an empty line)\n");
+            }
+            return out;
+        }
+        for node in &doc.body {
+            if let BodyKind::Text(text) = &node.kind {
+                out.push_str(&format!("text {text:?}\n"));
+            }
+        }
+        out.push_str(&render(line, &diagnostics));
+        out
+    }
+
+    fn command(&mut self, command: &str) -> String {
+        let (name, arg) = command
+            .split_once(char::is_whitespace)
+            .map_or((command, ""), |(n, a)| (n, a.trim()));
+        match name {
+            "tokens" => tokens(if arg.is_empty() { &self.last } else { arg }),
+            "schema" => schema(arg),
+            "help" | "h" => HELP.to_string(),
+            "quit" | "q" => {
+                self.done = true;
+                String::new()
+            }
+            _ => match suggest(name, COMMANDS.iter().copied()) {
+                Some(known) => format!("unknown command `:{name}`; did you mean `:{known}`?\n"),
+                None => format!("unknown command `:{name}`; try `:help`\n"),
+            },
+        }
+    }
+}
+
+/// Check the line of the question `src` starting at byte `start`.
+fn continue_question(src: &str, start: usize) -> String {
+    let (doc, diagnostics) = parse_document_recovering(src);
+    let mut out = String::new();
+    for node in doc.body.iter().filter(|n| n.span.start >= start) {
+        match &node.kind {
+            BodyKind::Directive(directive) => {
+                out.push_str(&show_directive(directive));
+                let mut diagnostics = validate_directive(src, directive);
+                diagnostics.retain(|d| d.span.start >= start);
+                out.push_str(&render(src, &diagnostics));
+            }
+            BodyKind::Text(text) => out.push_str(&format!("text {text:?}\n")),
+            BodyKind::Blank => {}
+        }
+    }
+    let new: Vec<Diagnostic> = diagnostics
+        .into_iter()
+        .filter(|d| d.span.start >= start)
+        .collect();
+    out.push_str(&render(src, &new));
+    out
+}
+
+/// Validate the finished question `src` and print what it reads as.
+fn finish(src: &str) -> String {
+    let (doc, mut diagnostics) = parse_document_recovering(src);
+    let (doc, evaluated) = evaluate(src, &doc);
+    diagnostics.extend(evaluated);
+    diagnostics.extend(validate_document(src, &doc));
+    let mut out = match read_question(src, &doc).0 {
+        Some(question) => format!("{question:#?}\n"),
+        None => String::new(),
+    };
+    if diagnostics.is_empty() {
+        out.push_str("ok\n");
+    }
+    out.push_str(&render(src, &diagnostics));
+    out
+}
+
+fn render(src: &str, diagnostics: &[Diagnostic]) -> String {
+    diagnostics
+        .iter()
+        .map(|d| format!("{}\n", d.render(src, ORIGIN)))
+        .collect()
+}
+
+/// A directive as an indented tree: its name, then each argument with the
+/// parameter it binds to, then each pair, values with their type.
+fn show_directive(directive: &Directive) -> String {
+    let mut out = format!("directive @{} {}\n", directive.name, span(&directive.span));
+    let positional = schema_for(&directive.name).map_or(&[][..], |s| s.positional);
+    for (i, arg) in directive.args.iter().enumerate() {
+        // As `bind_args` binds it: not when the parameter is also named.
+        let param = match positional.get(i) {
+            Some(name) if directive.get(name).is_none() => format!(" -> {name}"),
+            _ => String::new(),
+        };
+        out.push_str(&format!(
+            "  arg {} {}{param} {}\n",
+            arg.value,
+            type_name(&arg.value),
+            span(&arg.span)
+        ));
+    }
+    for pair in &directive.pairs {
+        out.push_str(&format!(
+            "  {} = {} {} {}\n",
+            pair.key,
+            pair.value,
+            type_name(&pair.value),
+            span(&(pair.key_span.start..pair.value_span.end))
+        ));
+    }
+    out
+}
+
+fn span(span: &Span) -> String {
+    format!("@{}..{}", span.start, span.end)
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Int(_) => "(int)",
+        Value::Float(_) => "(float)",
+        Value::Bool(_) => "(bool)",
+        Value::String(_) => "(string)",
+        Value::List(_) => "(list)",
+        Value::Map(_) => "(map)",
+    }
+}
+
+/// The lexer's tokens of `line`, one per row.
+fn tokens(line: &str) -> String {
+    highlight_line(line)
+        .iter()
+        .map(|t| {
+            let positional = if t.positional { " positional" } else { "" };
+            format!(
+                "{:>3}..{:<3} {:<13} {:?}{positional}\n",
+                t.start, t.end, t.kind, t.text
+            )
+        })
+        .collect()
+}
+
+/// The parameters of directive `name`, or every directive when empty.
+fn schema(name: &str) -> String {
+    let name = name.trim_start_matches('@');
+    if name.is_empty() {
+        return SCHEMAS
+            .iter()
+            .map(|s| format!("@{:<14} {}\n", s.name, s.doc))
+            .collect();
+    }
+    let Some(schema) = schema_for(name) else {
+        return match suggest(name, SCHEMAS.iter().map(|s| s.name)) {
+            Some(known) => format!("unknown directive `@{name}`; did you mean `@{known}`?\n"),
+            None => format!("unknown directive `@{name}`; `:schema` lists them\n"),
+        };
+    };
+    let mut out = format!("@{}: {}\n", schema.name, schema.doc);
+    if !schema.positional.is_empty() {
+        out.push_str(&format!("positional: {}\n", schema.positional.join(", ")));
+    }
+    if schema.open {
+        out.push_str("accepts any key\n");
+    }
+    for param in schema.params {
+        let mut notes = Vec::new();
+        if param.required {
+            notes.push("required".to_string());
+        }
+        if let Some(default) = param.default {
+            notes.push(format!("default {default}"));
+        }
+        if !param.allowed.is_empty() {
+            notes.push(format!("one of {}", param.allowed.join(", ")));
+        }
+        let notes = if notes.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", notes.join("; "))
+        };
+        out.push_str(&format!(
+            "  {:<12} {:<20} {}{notes}\n",
+            param.name,
+            param.ty.describe(),
+            param.doc
+        ));
+    }
+    out
+}
+
+/// Run a session on the terminal until `:quit` or end of input, keeping the
+/// history in `~/.q_history`.
+pub fn run() -> rustyline::Result<()> {
+    let mut editor = DefaultEditor::new()?;
+    let history =
+        std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".q_history"));
+    if let Some(path) = &history {
+        // A missing history file is not an error.
+        let _ = editor.load_history(path);
+    }
+    let mut session = Session::new();
+    println!("Type :help for help.");
+    while !session.is_done() {
+        match editor.readline(session.prompt()) {
+            Ok(line) => {
+                if !line.trim().is_empty() {
+                    editor.add_history_entry(line.as_str())?;
+                }
+                print!("{}", session.feed(&line));
+            }
+            Err(ReadlineError::Interrupted) => {
+                if session.cancel() {
+                    println!("(question discarded)");
+                }
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    if let Some(path) = &history {
+        editor.save_history(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_lines_and_commands() {
+        let mut session = Session::new();
+        assert_eq!(
+            session.feed("@meta tags=[a, b] level=3"),
+            "directive @meta @0..25\n  tags = [a, b] (list) @6..17\n  level = 3 (int) @18..25\n\
+             error[E0102]: unknown key `level` for `@meta`\n \
+             --> <repl>:1:19\n  |\n1 | @meta tags=[a, b] level=3\n  |                   ^^^^^\n  |\n  \
+             = help: expected one of `author`, `tags`, `difficulty`\n\n"
+        );
+        assert_eq!(
+            session.feed(":tokens"),
+            session.feed(":tokens @meta tags=[a, b] level=3")
+        );
+        let tokens = session.feed(":tokens @include 'a.q'");
+        assert_eq!(
+            tokens,
+            "  0..1   At            \"@\"\n  1..8   Ident         \"include\"\n  \
+             8..9   Ws            \" \"\n  9..14  String        \"'a.q'\" positional\n"
+        );
+        let schema = session.feed(":schema option");
+        assert!(schema.starts_with("@option: Single choice"), "{schema}");
+        assert!(schema.contains("positional: prompt\n"), "{schema}");
+        assert!(
+            schema.contains("  points       a positive integer   Points awarded for a correct answer. [default 1]\n"),
+            "{schema}"
+        );
+        assert_eq!(
+            session.feed(":schema opton"),
+            "unknown directive `@opton`; did you mean `@option`?\n"
+        );
+        assert_eq!(
+            session.feed(":tokns"),
+            "unknown command `:tokns`; did you mean `:tokens`?\n"
+        );
+        assert!(!session.is_done());
+        session.feed(":q");
+        assert!(session.is_done());
+    }
+
+    #[test]
+    fn multi_line_question() {
+        let mut session = Session::new();
+        let out = session.feed("@option \"Ibu kota Jepang?\" points=2");
+        assert!(
+            out.starts_with(
+                "directive @option @0..35\n  arg \"Ibu kota Jepang?\" (string) -> prompt @8..26\n"
+            ),
+            "{out}"
+        );
+        assert!(
+            out.ends_with("(question started: enter its body, then an empty line)\n"),
+            "{out}"
+        );
+        assert_eq!(session.prompt(), ".. ");
+        assert_eq!(session.feed("* Tokyo"), "text \"* Tokyo\"\n");
+        let out = session.feed("@meta difficulty=hardest");
+        assert!(out.contains("error[E0105]"), "{out}");
+        assert!(out.contains("--> <repl>:3:18"), "{out}");
+        assert_eq!(session.feed("- Kyoto"), "text \"- Kyoto\"\n");
+        let out = session.feed("");
+        assert!(
+            out.starts_with("Question {\n    prompt: \"Ibu kota Jepang?\""),
+            "{out}"
+        );
+        assert!(out.contains("text: \"Kyoto\""), "{out}");
+        assert_eq!(session.prompt(), "q> ");
+
+        session.feed("@option");
+        assert!(session.cancel());
+        assert_eq!(session.prompt(), "q> ");
+    }
+}
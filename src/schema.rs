@@ -0,0 +1,880 @@
+use crate::diagnostic::{Diagnostic, codes};
+use crate::eval::DEFINE;
+use crate::include::INCLUDE;
+use crate::line_index::{Encoding, LineIndex};
+use crate::parser::{BodyKind, Directive, Document, Pair, parse_header_recovering};
+use crate::question::validate_question;
+use crate::value::Value;
+
+/// The type a directive parameter accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamType {
+    Int,
+    /// An integer greater than zero.
+    PositiveInt,
+    /// A float; integers are accepted too.
+    Float,
+    Bool,
+    String,
+    List,
+    Map,
+}
+
+impl ParamType {
+    /// Description used in "`key` must be ..." messages.
+    pub fn describe(self) -> &'static str {
+        match self {
+            ParamType::Int => "an integer",
+            ParamType::PositiveInt => "a positive integer",
+            ParamType::Float => "a number",
+            ParamType::Bool => "a boolean",
+            ParamType::String => "a string",
+            ParamType::List => "a list",
+            ParamType::Map => "a map",
+        }
+    }
+
+    pub fn accepts(self, value: &Value) -> bool {
+        match (self, value) {
+            (ParamType::Int, Value::Int(_)) => true,
+            (ParamType::PositiveInt, Value::Int(i)) => *i > 0,
+            (ParamType::Float, Value::Int(_) | Value::Float(_)) => true,
+            (ParamType::Bool, Value::Bool(_)) => true,
+            (ParamType::String, Value::String(_)) => true,
+            (ParamType::List, Value::List(_)) => true,
+            (ParamType::Map, Value::Map(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// One named parameter of a directive.
+#[derive(Debug, Clone, Copy)]
+pub struct ParamSpec {
+    pub name: &'static str,
+    pub ty: ParamType,
+    pub required: bool,
+    /// Default in DSL spelling, used when the key is absent.
+    pub default: Option<&'static str>,
+    /// For string parameters: the accepted values. Empty means any.
+    pub allowed: &'static [&'static str],
+    pub doc: &'static str,
+}
+
+impl ParamSpec {
+    pub fn default_value(&self) -> Option<Value> {
+        self.default.map(Value::from_bare)
+    }
+}
+
+/// The parameters a directive declares.
+#[derive(Debug, Clone, Copy)]
+pub struct DirectiveSchema {
+    pub name: &'static str,
+    pub doc: &'static str,
+    pub params: &'static [ParamSpec],
+    /// Whether the directive may appear more than once in the header block.
+    pub repeatable: bool,
+    /// Whether the directive must be the first line of the header block,
+    /// not counting preamble directives.
+    pub first: bool,
+    /// Whether the directive sets up the file rather than describing the
+    /// question, so it may come before a `first` directive, as `@define` and
+    /// `@include` do.
+    pub preamble: bool,
+    /// Directives that may not share a header block with this one.
+    pub conflicts: &'static [&'static str],
+    /// Whether keys outside `params` are accepted, as `@define` names its
+    /// variables with them.
+    pub open: bool,
+    /// Parameters that may also be given as positional arguments, in order:
+    /// with `["prompt"]`, `@option "Why?"` means `@option prompt="Why?"`.
+    pub positional: &'static [&'static str],
+}
+
+impl DirectiveSchema {
+    pub fn param(&self, name: &str) -> Option<&'static ParamSpec> {
+        self.params.iter().find(|p| p.name == name)
+    }
+}
+
+const PROMPT: ParamSpec = ParamSpec {
+    name: "prompt",
+    ty: ParamType::String,
+    required: false,
+    default: None,
+    allowed: &[],
+    doc: "Question text shown above the answers.",
+};
+
+const POINTS: ParamSpec = ParamSpec {
+    name: "points",
+    ty: ParamType::PositiveInt,
+    required: false,
+    default: Some("1"),
+    allowed: &[],
+    doc: "Points awarded for a correct answer.",
+};
+
+const SHUFFLE: ParamSpec = ParamSpec {
+    name: "shuffle",
+    ty: ParamType::Bool,
+    required: false,
+    default: Some("false"),
+    allowed: &[],
+    doc: "Present the answers in random order.",
+};
+
+const QUESTION_TYPES: &[&str] = &["option", "multi_option", "matching_pair"];
+
+/// Schemas for every known directive: one per [`crate::import::Import`]
+/// question type, which opens the header block, plus the header metadata
+/// directives that may follow it, `@include` and `@define`.
+pub static SCHEMAS: &[DirectiveSchema] = &[
+    DirectiveSchema {
+        name: "option",
+        doc: "Single choice question: exactly one answer is correct.",
+        params: &[PROMPT, POINTS, SHUFFLE],
+        repeatable: false,
+        first: true,
+        preamble: false,
+        conflicts: QUESTION_TYPES,
+        open: false,
+        positional: &["prompt"],
+    },
+    DirectiveSchema {
+        name: "multi_option",
+        doc: "Multiple choice question: any number of answers may be correct.",
+        params: &[
+            PROMPT,
+            POINTS,
+            SHUFFLE,
+            ParamSpec {
+                name: "scoring",
+                ty: ParamType::String,
+                required: false,
+                default: Some("all_or_nothing"),
+                allowed: &["all_or_nothing", "partial"],
+                doc: "How partially correct selections are graded.",
+            },
+        ],
+        repeatable: false,
+        first: true,
+        preamble: false,
+        conflicts: QUESTION_TYPES,
+        open: false,
+        positional: &["prompt"],
+    },
+    DirectiveSchema {
+        name: "matching_pair",
+        doc: "Matching question: pair every left item with its right item.",
+        params: &[
+            PROMPT,
+            POINTS,
+            SHUFFLE,
+            ParamSpec {
+                name: "count",
+                ty: ParamType::PositiveInt,
+                required: true,
+                default: None,
+                allowed: &[],
+                doc: "Number of pairs shown to the student.",
+            },
+        ],
+        repeatable: false,
+        first: true,
+        preamble: false,
+        conflicts: QUESTION_TYPES,
+        open: false,
+        positional: &["prompt"],
+    },
+    DirectiveSchema {
+        name: "meta",
+        doc: "Information about the question that students do not see.",
+        params: &[
+            ParamSpec {
+                name: "author",
+                ty: ParamType::String,
+                required: false,
+                default: None,
+                allowed: &[],
+                doc: "Who wrote the question.",
+            },
+            ParamSpec {
+                name: "tags",
+                ty: ParamType::List,
+                required: false,
+                default: None,
+                allowed: &[],
+                doc: "Topics used to search and group questions.",
+            },
+            ParamSpec {
+                name: "difficulty",
+                ty: ParamType::String,
+                required: false,
+                default: None,
+                allowed: &["easy", "medium", "hard"],
+                doc: "How hard the question is expected to be.",
+            },
+        ],
+        repeatable: true,
+        first: false,
+        preamble: false,
+        conflicts: &[],
+        open: false,
+        positional: &[],
+    },
+    DirectiveSchema {
+        name: "scoring",
+        doc: "Grading settings that override the question's own.",
+        params: &[
+            POINTS,
+            ParamSpec {
+                name: "penalty",
+                ty: ParamType::Float,
+                required: false,
+                default: Some("0"),
+                allowed: &[],
+                doc: "Points deducted for a wrong answer.",
+            },
+        ],
+        repeatable: false,
+        first: false,
+        preamble: false,
+        conflicts: &[],
+        open: false,
+        positional: &[],
+    },
+    DirectiveSchema {
+        name: INCLUDE,
+        doc: "Insert the lines of another file in place of this one.",
+        params: &[ParamSpec {
+            name: "path",
+            ty: ParamType::String,
+            required: true,
+            default: None,
+            allowed: &[],
+            doc: "File to include, relative to this one.",
+        }],
+        repeatable: true,
+        first: false,
+        preamble: true,
+        conflicts: &[],
+        open: false,
+        positional: &["path"],
+    },
+    DirectiveSchema {
+        name: DEFINE,
+        doc: "Define variables, used as `${name}` in values and body text.",
+        params: &[],
+        repeatable: true,
+        first: false,
+        preamble: true,
+        conflicts: &[],
+        open: true,
+        positional: &[],
+    },
+];
+
+pub fn schema_for(name: &str) -> Option<&'static DirectiveSchema> {
+    SCHEMAS.iter().find(|s| s.name == name)
+}
+
+/// Whether `directive` is a preamble directive (see
+/// [`DirectiveSchema::preamble`]).
+pub fn is_preamble(directive: &Directive) -> bool {
+    schema_for(&directive.name).is_some_and(|s| s.preamble)
+}
+
+/// The first of `headers` that is not a preamble directive: the one a
+/// question is read from.
+pub fn main_directive(headers: &[Directive]) -> Option<&Directive> {
+    headers.iter().find(|d| !is_preamble(d))
+}
+
+/// How a directive that repeats a key, as in `@option points=1 points=5`, is
+/// read. Validation, [`crate::de::from_str_with`] and the language server
+/// read it the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateKeys {
+    /// Report every repeat, pointing at the first use.
+    #[default]
+    Error,
+    /// Accept repeats; the last pair wins, as [`Directive::get`] reads it.
+    LastWins,
+    /// Accept repeats and collect their values, in order, into a list. Each
+    /// value is validated against the parameter on its own.
+    Collect,
+}
+
+/// Options for [`validate_document_with`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ValidateOptions {
+    pub duplicate_keys: DuplicateKeys,
+}
+
+/// `directive` with every repeated key reduced to one pair as `policy` reads
+/// it. `LastWins` keeps the last pair; `Collect` keeps the first, holding a
+/// list of all the values, with the first value's span. `Error` changes
+/// nothing.
+pub fn merge_duplicates(directive: &Directive, policy: DuplicateKeys) -> Directive {
+    let mut merged = directive.clone();
+    match policy {
+        DuplicateKeys::Error => {}
+        DuplicateKeys::LastWins => {
+            merged.pairs = directive
+                .pairs
+                .iter()
+                .enumerate()
+                .filter(|(i, p)| directive.pairs[i + 1..].iter().all(|q| q.key != p.key))
+                .map(|(_, p)| p.clone())
+                .collect();
+        }
+        DuplicateKeys::Collect => {
+            merged.pairs = Vec::new();
+            for pair in &directive.pairs {
+                if merged.pairs.iter().any(|p| p.key == pair.key) {
+                    continue;
+                }
+                let all: Vec<&Pair> = directive.get_all(&pair.key).collect();
+                let mut pair = pair.clone();
+                if all.len() > 1 {
+                    pair.value = Value::List(all.iter().map(|p| p.value.clone()).collect());
+                }
+                merged.pairs.push(pair);
+            }
+        }
+    }
+    merged
+}
+
+/// `directive` with its positional arguments turned into pairs named after
+/// the schema's positional parameters, placed before the written pairs, so
+/// readers only need to look at `pairs`. An argument stays in `args` when
+/// there is no parameter for it or its parameter is also given by name.
+pub fn bind_args(directive: &Directive) -> Directive {
+    let positional = schema_for(&directive.name).map_or(&[][..], |s| s.positional);
+    let mut bound = directive.clone();
+    let (mut args, mut pairs) = (Vec::new(), Vec::new());
+    for (i, arg) in bound.args.drain(..).enumerate() {
+        match positional.get(i) {
+            Some(name) if directive.get(name).is_none() => pairs.push(Pair {
+                key: name.to_string(),
+                value: arg.value,
+                key_span: arg.span.clone(),
+                value_span: arg.span,
+            }),
+            _ => args.push(arg),
+        }
+    }
+    pairs.append(&mut bound.pairs);
+    bound.args = args;
+    bound.pairs = pairs;
+    bound
+}
+
+/// Check one directive against its schema with the default options. The
+/// parts of a recovered directive that are missing or broken are skipped;
+/// their parse errors cover them.
+pub fn validate_directive(src: &str, directive: &Directive) -> Vec<Diagnostic> {
+    check_directive(&LineIndex::new(src), directive, &ValidateOptions::default())
+}
+
+fn check_directive(
+    src: &LineIndex,
+    directive: &Directive,
+    options: &ValidateOptions,
+) -> Vec<Diagnostic> {
+    if directive.errors.contains(&directive.name_span) {
+        return Vec::new();
+    }
+    let written = directive;
+    let directive = &bind_args(directive);
+    let Some(schema) = schema_for(&directive.name) else {
+        let mut diag = Diagnostic::error(
+            src,
+            codes::UNKNOWN_DIRECTIVE,
+            directive.name_span.clone(),
+            format!("unknown directive `@{}`", directive.name),
+        );
+        if let Some(name) = suggest(&directive.name, SCHEMAS.iter().map(|s| s.name)) {
+            diag = diag.with_help(format!("did you mean `@{name}`?"));
+        }
+        return vec![diag];
+    };
+    let mut diagnostics = Vec::new();
+    for (i, arg) in written.args.iter().enumerate() {
+        let diag = match schema.positional.get(i) {
+            Some(name) if written.get(name).is_some() => Diagnostic::error(
+                src,
+                codes::UNEXPECTED_ARGUMENT,
+                arg.span.clone(),
+                format!("`{name}` is given both as an argument and as `{name}=`"),
+            )
+            .with_help("remove one of them"),
+            Some(_) => continue,
+            None => Diagnostic::error(
+                src,
+                codes::UNEXPECTED_ARGUMENT,
+                arg.span.clone(),
+                format!("unexpected positional argument for `@{}`", schema.name),
+            )
+            .with_help(match schema.positional {
+                [] => format!("`@{}` takes only `key=value` pairs", schema.name),
+                names => format!(
+                    "`@{}` takes {} positional argument{}: {}",
+                    schema.name,
+                    names.len(),
+                    if names.len() == 1 { "" } else { "s" },
+                    names
+                        .iter()
+                        .map(|n| format!("`{n}`"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            }),
+        };
+        diagnostics.push(diag);
+    }
+    // An open schema's keys are variable names, which evaluation checks.
+    if options.duplicate_keys == DuplicateKeys::Error && !schema.open {
+        for (i, pair) in directive.pairs.iter().enumerate() {
+            if let Some(first) = directive.pairs[..i].iter().find(|p| p.key == pair.key) {
+                diagnostics.push(
+                    Diagnostic::error(
+                        src,
+                        codes::DUPLICATE_KEY,
+                        pair.key_span.clone(),
+                        format!("duplicate key `{}` for `@{}`", pair.key, schema.name),
+                    )
+                    .with_related(src, first.key_span.clone(), "first used here")
+                    .with_help("remove one of them"),
+                );
+            }
+        }
+    }
+    let unmerged = directive;
+    let directive = &merge_duplicates(directive, options.duplicate_keys);
+    for pair in &directive.pairs {
+        let Some(param) = schema.param(&pair.key) else {
+            if schema.open {
+                continue;
+            }
+            let mut diag = Diagnostic::error(
+                src,
+                codes::UNKNOWN_KEY,
+                pair.key_span.clone(),
+                format!("unknown key `{}` for `@{}`", pair.key, schema.name),
+            );
+            diag = match suggest(&pair.key, schema.params.iter().map(|p| p.name)) {
+                Some(key) => diag.with_help(format!("did you mean `{key}`?")),
+                None => diag.with_help(format!("expected one of {}", key_list(schema))),
+            };
+            diagnostics.push(diag);
+            continue;
+        };
+        // Collected values are checked one by one, each against the
+        // parameter's type.
+        let values: Vec<&Pair> = match options.duplicate_keys {
+            DuplicateKeys::Collect => unmerged.get_all(&pair.key).collect(),
+            _ => vec![pair],
+        };
+        for pair in values {
+            if directive.is_recovered(&pair.value_span) {
+                continue;
+            }
+            diagnostics.extend(check_value(src, param, pair));
+        }
+    }
+    for param in schema.params.iter().filter(|p| p.required) {
+        if directive.get(param.name).is_none() {
+            diagnostics.push(
+                Diagnostic::error(
+                    src,
+                    codes::MISSING_KEY,
+                    directive.span.clone(),
+                    format!(
+                        "missing required key `{}` for `@{}`",
+                        param.name, schema.name
+                    ),
+                )
+                .with_help(format!("add `{}=...`: {}", param.name, param.doc)),
+            );
+        }
+    }
+    diagnostics
+}
+
+/// Check one value of `param` against its type and allowed values.
+fn check_value(src: &LineIndex, param: &ParamSpec, pair: &Pair) -> Option<Diagnostic> {
+    if !param.ty.accepts(&pair.value) {
+        Some(
+            Diagnostic::error(
+                src,
+                codes::TYPE_MISMATCH,
+                pair.value_span.clone(),
+                format!("`{}` must be {}", param.name, param.ty.describe()),
+            )
+            .with_expected(vec![param.ty.describe().to_string()])
+            .with_found(pair.value.type_name()),
+        )
+    } else if let Some(value) = pair.value.as_str()
+        && !param.allowed.is_empty()
+        && !param.allowed.contains(&value)
+    {
+        Some(
+            Diagnostic::error(
+                src,
+                codes::DISALLOWED_VALUE,
+                pair.value_span.clone(),
+                format!("`{value}` is not a valid value for `{}`", param.name),
+            )
+            .with_expected(param.allowed.iter().map(|a| format!("`{a}`")).collect())
+            .with_found(format!("`{value}`")),
+        )
+    } else {
+        None
+    }
+}
+
+/// Check the header block against each schema's repeat, placement and
+/// conflict rules. Each directive gets at most one of these diagnostics.
+pub fn validate_header(src: &str, headers: &[Directive]) -> Vec<Diagnostic> {
+    header_rules(&LineIndex::new(src), headers)
+}
+
+fn header_rules(index: &LineIndex, headers: &[Directive]) -> Vec<Diagnostic> {
+    let line_of = |d: &Directive| index.line_col(d.span.start, Encoding::Char).line + 1;
+    let mut diagnostics = Vec::new();
+    for (i, directive) in headers.iter().enumerate() {
+        let Some(schema) = schema_for(&directive.name) else {
+            continue;
+        };
+        let earlier = &headers[..i];
+        if let Some(prev) = earlier.iter().find(|d| d.name == directive.name)
+            && !schema.repeatable
+        {
+            diagnostics.push(
+                Diagnostic::error(
+                    index,
+                    codes::DUPLICATE_DIRECTIVE,
+                    directive.name_span.clone(),
+                    format!("`@{}` may only appear once", schema.name),
+                )
+                .with_help(format!("first used on line {}", line_of(prev))),
+            );
+        } else if let Some(prev) = earlier
+            .iter()
+            .find(|d| schema.conflicts.contains(&d.name.as_str()) && d.name != directive.name)
+        {
+            diagnostics.push(
+                Diagnostic::error(
+                    index,
+                    codes::CONFLICTING_DIRECTIVE,
+                    directive.name_span.clone(),
+                    format!("`@{}` conflicts with `@{}`", schema.name, prev.name),
+                )
+                .with_help(format!(
+                    "`@{}` is on line {}",
+                    prev.name,
+                    line_of(prev)
+                )),
+            );
+        } else if schema.first
+            && let Some(prev) = earlier.iter().find(|d| !is_preamble(d))
+        {
+            diagnostics.push(
+                Diagnostic::error(
+                    index,
+                    codes::MISPLACED_DIRECTIVE,
+                    directive.name_span.clone(),
+                    format!("`@{}` must be the first line of the header", schema.name),
+                )
+                .with_help(format!("move it above `@{}`", prev.name)),
+            );
+        }
+    }
+    diagnostics
+}
+
+/// Validate every directive in a parsed document, the header block rules and
+/// the question body. Pass the document through [`crate::eval::evaluate`]
+/// first so interpolated values are checked as they resolve.
+pub fn validate_document(src: &str, doc: &Document) -> Vec<Diagnostic> {
+    validate_document_with(src, doc, &ValidateOptions::default())
+}
+
+pub fn validate_document_with(
+    src: &str,
+    doc: &Document,
+    options: &ValidateOptions,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = validate_directives(src, doc, options);
+    diagnostics.extend(validate_question(src, doc));
+    diagnostics
+}
+
+/// [`validate_document_with`] without the question body checks.
+pub(crate) fn validate_directives(
+    src: &str,
+    doc: &Document,
+    options: &ValidateOptions,
+) -> Vec<Diagnostic> {
+    let body = doc.body.iter().filter_map(|node| match &node.kind {
+        BodyKind::Directive(d) => Some(d),
+        _ => None,
+    });
+    let index = LineIndex::new(src);
+    let mut diagnostics: Vec<Diagnostic> = doc
+        .headers
+        .iter()
+        .flat_map(|d| check_directive(&index, d, options))
+        .collect();
+    diagnostics.extend(header_rules(&index, &doc.headers));
+    diagnostics.extend(body.flat_map(|d| check_directive(&index, d, options)));
+    diagnostics
+}
+
+/// Parse the header of `src` and validate it. Parse errors come first; a
+/// broken header is still validated as far as it could be recovered.
+pub fn validate_first_line(src: &str) -> Vec<Diagnostic> {
+    let (header, mut diagnostics) = parse_header_recovering(src);
+    if let Some(directive) = header {
+        diagnostics.extend(validate_directive(src, &directive));
+    }
+    diagnostics
+}
+
+fn key_list(schema: &DirectiveSchema) -> String {
+    schema
+        .params
+        .iter()
+        .map(|p| format!("`{}`", p.name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Closest candidate within edit distance 2, for "did you mean" hints.
+pub(crate) fn suggest<'a>(
+    name: &str,
+    candidates: impl Iterator<Item = &'a str>,
+) -> Option<&'a str> {
+    candidates
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(d, _)| *d <= 2)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                prev
+            } else {
+                1 + prev.min(row[j]).min(cur)
+            };
+            prev = cur;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::Import;
+    use strum::VariantNames;
+
+    #[test]
+    fn every_import_has_a_schema() {
+        for name in Import::VARIANTS {
+            assert!(schema_for(name).is_some(), "no schema for {name}");
+        }
+    }
+
+    #[test]
+    fn located_diagnostics() {
+        let src = "@matching_pair cout=3 points=0 shuffle=yes\n";
+        let diags = validate_first_line(src);
+        let summary: Vec<_> = diags
+            .iter()
+            .map(|d| (d.code, d.message.as_str(), &src[d.span.clone()]))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    codes::UNKNOWN_KEY,
+                    "unknown key `cout` for `@matching_pair`",
+                    "cout"
+                ),
+                (
+                    codes::TYPE_MISMATCH,
+                    "`points` must be a positive integer",
+                    "0"
+                ),
+                (codes::TYPE_MISMATCH, "`shuffle` must be a boolean", "yes"),
+                (
+                    codes::MISSING_KEY,
+                    "missing required key `count` for `@matching_pair`",
+                    "@matching_pair cout=3 points=0 shuffle=yes"
+                ),
+            ]
+        );
+        assert_eq!(diags[0].help.as_deref(), Some("did you mean `count`?"));
+
+        let diags = validate_first_line("@multi_option scoring=most\n");
+        assert_eq!(diags[0].code, codes::DISALLOWED_VALUE);
+        assert_eq!(
+            validate_first_line("@opton\n")[0].help.as_deref(),
+            Some("did you mean `@option`?")
+        );
+        assert!(validate_first_line("@option points=2 shuffle=true prompt=\"2+2?\"").is_empty());
+        // A half-typed header is still validated past its parse error.
+        let codes: Vec<_> = validate_first_line("@option shufle=true points=")
+            .iter()
+            .map(|d| d.code)
+            .collect();
+        assert_eq!(codes, [codes::MISSING_VALUE, codes::UNKNOWN_KEY]);
+
+        // Positional arguments bind to the schema's positional parameters.
+        assert!(validate_first_line("@option \"2+2?\" points=2").is_empty());
+        let src = "@option 4 extra\n";
+        let summary: Vec<_> = validate_first_line(src)
+            .into_iter()
+            .map(|d| (d.code, d.help, &src[d.span]))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    codes::UNEXPECTED_ARGUMENT,
+                    Some("`@option` takes 1 positional argument: `prompt`".into()),
+                    "extra"
+                ),
+                (codes::TYPE_MISMATCH, None, "4"),
+            ]
+        );
+        let diags = validate_first_line("@option Why? prompt=Why?");
+        assert_eq!(
+            diags[0].message,
+            "`prompt` is given both as an argument and as `prompt=`"
+        );
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        let src = "@option points=1 prompt=Q points=5\n";
+        let Ok(Some(header)) = crate::parser::parse_header(src) else {
+            panic!("expected directive");
+        };
+        assert_eq!(header.get("points").unwrap().value, Value::Int(5));
+        assert_eq!(header.get_all("points").count(), 2);
+
+        let diags = validate_first_line(src);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].code, codes::DUPLICATE_KEY);
+        assert_eq!(&src[diags[0].span.clone()], "points");
+        assert_eq!(diags[0].span.start, 26);
+        assert_eq!(
+            diags[0].render(src, "q.dsl"),
+            "error[E0110]: duplicate key `points` for `@option`\n \
+             --> q.dsl:1:27\n  |\n\
+             1 | @option points=1 prompt=Q points=5\n  \
+             |                           ^^^^^^\n  \
+             |         ------ first used here\n  |\n  \
+             = help: remove one of them\n"
+        );
+
+        let doc = crate::parser::parse_document(src).unwrap();
+        let with = |duplicate_keys| {
+            let options = ValidateOptions { duplicate_keys };
+            validate_document_with(src, &doc, &options)
+                .into_iter()
+                .filter(|d| d.code.starts_with("E01"))
+                .map(|d| (d.code, &src[d.span]))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(with(DuplicateKeys::LastWins), []);
+        // Each collected value is checked against the parameter's type.
+        assert_eq!(with(DuplicateKeys::Collect), []);
+        let options = ValidateOptions {
+            duplicate_keys: DuplicateKeys::Collect,
+        };
+        let src = "@option points=x points=2 points=0\n@meta difficulty=easy difficulty=hardest\n";
+        let doc = crate::parser::parse_document(src).unwrap();
+        let collected: Vec<_> = validate_document_with(src, &doc, &options)
+            .into_iter()
+            .filter(|d| d.code.starts_with("E01"))
+            .map(|d| (d.code, &src[d.span]))
+            .collect();
+        assert_eq!(
+            collected,
+            [
+                (codes::TYPE_MISMATCH, "x"),
+                (codes::TYPE_MISMATCH, "0"),
+                (codes::DISALLOWED_VALUE, "hardest"),
+            ]
+        );
+        let merged = merge_duplicates(&header, DuplicateKeys::Collect);
+        assert_eq!(merged.pairs[0].value, Value::List(vec![1.into(), 5.into()]));
+        let last = merge_duplicates(&header, DuplicateKeys::LastWins);
+        let keys: Vec<_> = last.pairs.iter().map(|p| p.key.as_str()).collect();
+        assert_eq!(keys, ["prompt", "points"]);
+    }
+
+    #[test]
+    fn header_block_rules() {
+        let check = |src: &str| {
+            let doc = crate::parser::parse_document(src).unwrap();
+            let (doc, _) = crate::eval::evaluate(src, &doc);
+            validate_document(src, &doc)
+                .into_iter()
+                .map(|d| (d.code, d.line, d.help.unwrap_or_default()))
+                .collect::<Vec<_>>()
+        };
+        assert!(
+            check("@option\n@meta author=ana\n@meta tags=[x]\n@scoring penalty=0.5\nQ?\n* a\n- b")
+                .is_empty()
+        );
+        assert_eq!(
+            check("@meta author=ana\n@option\n@scoring\n@multi_option\n@scoring points=2\n"),
+            [
+                (
+                    codes::MISPLACED_DIRECTIVE,
+                    2,
+                    "move it above `@meta`".to_string()
+                ),
+                (
+                    codes::CONFLICTING_DIRECTIVE,
+                    4,
+                    "`@option` is on line 2".to_string()
+                ),
+                (
+                    codes::DUPLICATE_DIRECTIVE,
+                    5,
+                    "first used on line 3".to_string()
+                ),
+            ]
+        );
+        // The rules cover the header block only.
+        assert!(check("@option\n\n@option\nQ?\n* a\n- b\n").is_empty());
+        // `@define` and `@include` may come before the question directive.
+        assert!(check("@define n=2\n@option points=${n}\nQ?\n* a\n- b\n").is_empty());
+        assert!(check("@include path=x.dsl\n@define n=2\n@option\nQ?\n* a\n- b\n").is_empty());
+        assert_eq!(
+            check("@define n=2\n@meta author=ana\n@option\n"),
+            [(
+                codes::MISPLACED_DIRECTIVE,
+                3,
+                "move it above `@meta`".to_string()
+            )]
+        );
+    }
+}
@@ -0,0 +1,471 @@
+//! Semantic tokens for a whole document.
+//!
+//! [`semantic_tokens`] classifies every meaningful token of the lossless tree
+//! (see [`crate::syntax`]); whitespace and line terminators are skipped.
+//! Positions are given both as byte spans and as 0-based line / UTF-16 column
+//! pairs, which is what the browser and LSP clients count in.
+//! [`encode_delta`] packs the tokens into the LSP `SemanticTokens.data` layout.
+
+use serde::Serialize;
+use strum::VariantNames;
+
+use crate::eval::interpolations;
+use crate::line_index::{Encoding, LineIndex};
+use crate::parser::{Span, parse_value};
+use crate::schema::{DirectiveSchema, ParamSpec, schema_for};
+use crate::syntax::{SyntaxKind, SyntaxNode, SyntaxToken, parse_syntax};
+use crate::value::Value;
+
+/// Token types. The discriminant is the index into [`TokenKind::VARIANTS`],
+/// which serves as the legend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, VariantNames)]
+#[serde(rename_all = "camelCase")]
+#[strum(serialize_all = "camelCase")]
+pub enum TokenKind {
+    /// The `@` and name of a directive.
+    Directive,
+    Key,
+    /// `=`.
+    Operator,
+    String,
+    Number,
+    Boolean,
+    /// Any other bare word value.
+    Value,
+    /// `[`, `]`, `{`, `}` and `,`.
+    Punctuation,
+    /// A plain text line.
+    Text,
+    /// A `#` or `//` comment.
+    Comment,
+    /// A `${name}` variable reference.
+    Variable,
+}
+
+/// Token modifiers. The discriminant is the bit in the encoded bitset and the
+/// index into [`TokenModifier::VARIANTS`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, VariantNames)]
+#[serde(rename_all = "camelCase")]
+#[strum(serialize_all = "camelCase")]
+pub enum TokenModifier {
+    /// A header block directive, which declares the question.
+    Declaration,
+    /// Input that does not parse or that the schema rejects.
+    Invalid,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SemanticToken {
+    pub kind: TokenKind,
+    pub modifiers: Vec<TokenModifier>,
+    /// Byte range in the source.
+    pub span: Span,
+    /// 0-based line.
+    pub line: u32,
+    /// Start column in UTF-16 code units.
+    pub start: u32,
+    /// Length in UTF-16 code units.
+    pub length: u32,
+}
+
+impl SemanticToken {
+    pub fn modifier_bits(&self) -> u32 {
+        self.modifiers
+            .iter()
+            .fold(0, |bits, m| bits | 1 << *m as u32)
+    }
+}
+
+/// Names of token types and modifiers, in the shape of an LSP `SemanticTokensLegend`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Legend {
+    pub token_types: &'static [&'static str],
+    pub token_modifiers: &'static [&'static str],
+}
+
+pub const LEGEND: Legend = Legend {
+    token_types: TokenKind::VARIANTS,
+    token_modifiers: TokenModifier::VARIANTS,
+};
+
+/// Classify the tokens of every line of `src`, in source order.
+pub fn semantic_tokens(src: &str) -> Vec<SemanticToken> {
+    let mut out = Collector {
+        index: LineIndex::new(src),
+        tokens: Vec::new(),
+    };
+    // The header block is the first run of directive lines, after any blank lines.
+    let (mut in_header, mut header_seen) = (true, false);
+    for node in parse_syntax(src).children() {
+        match node.kind() {
+            SyntaxKind::Directive => header_seen = true,
+            SyntaxKind::BlankLine if !header_seen => {}
+            SyntaxKind::CommentLine => {}
+            _ => in_header = false,
+        }
+        match node.kind() {
+            SyntaxKind::Directive => out.directive(&node, in_header),
+            SyntaxKind::TextLine | SyntaxKind::CommentLine => {
+                for token in tokens(&node) {
+                    if token.kind() == SyntaxKind::Text {
+                        out.push_split(TokenKind::Text, token_span(&token), token.text(), &[]);
+                    } else {
+                        out.push(TokenKind::Comment, token_span(&token), Vec::new());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    out.tokens
+}
+
+/// Encode `tokens` as LSP delta quintuples: line delta, start delta (relative
+/// to the previous token when on the same line), length, type, modifier bits.
+pub fn encode_delta(tokens: &[SemanticToken]) -> Vec<u32> {
+    let mut data = Vec::with_capacity(tokens.len() * 5);
+    let (mut line, mut start) = (0, 0);
+    for token in tokens {
+        let delta_line = token.line - line;
+        let delta_start = if delta_line == 0 {
+            token.start - start
+        } else {
+            token.start
+        };
+        data.extend([
+            delta_line,
+            delta_start,
+            token.length,
+            token.kind as u32,
+            token.modifier_bits(),
+        ]);
+        (line, start) = (token.line, token.start);
+    }
+    data
+}
+
+/// [`semantic_tokens`] as a JSON array.
+pub fn semantic_tokens_json(src: &str) -> String {
+    serde_json::to_string(&semantic_tokens(src)).expect("tokens serialize to JSON")
+}
+
+/// Whether the value written as `text` does not parse or `param` rejects it.
+fn rejected(param: Option<&ParamSpec>, text: &str) -> bool {
+    match (param, parse_value(text)) {
+        (_, None) => true,
+        // The type is only known once variables are resolved.
+        _ if interpolations(text).next().is_some() => false,
+        (Some(p), Some(v)) => {
+            !p.ty.accepts(&v)
+                || v.as_str()
+                    .is_some_and(|s| !p.allowed.is_empty() && !p.allowed.contains(&s))
+        }
+        _ => false,
+    }
+}
+
+struct Collector {
+    index: LineIndex,
+    tokens: Vec<SemanticToken>,
+}
+
+fn token_span(token: &SyntaxToken) -> Span {
+    let range = token.text_range();
+    range.start().into()..range.end().into()
+}
+
+fn node_span(node: &SyntaxNode) -> Span {
+    let range = node.text_range();
+    range.start().into()..range.end().into()
+}
+
+fn tokens(node: &SyntaxNode) -> impl Iterator<Item = SyntaxToken> + use<> {
+    node.descendants_with_tokens()
+        .filter_map(|e| e.into_token())
+        .filter(|t| !matches!(t.kind(), SyntaxKind::Whitespace | SyntaxKind::Newline))
+}
+
+impl Collector {
+    fn push(&mut self, kind: TokenKind, span: Span, modifiers: Vec<TokenModifier>) {
+        // Tokens never span lines.
+        let start = self.index.line_col(span.start, Encoding::Utf16);
+        let end = self.index.line_col(span.end, Encoding::Utf16);
+        self.tokens.push(SemanticToken {
+            kind,
+            modifiers,
+            line: start.line,
+            start: start.col,
+            length: end.col - start.col,
+            span,
+        });
+    }
+
+    /// Push `text`, at `span`, as `kind` tokens with its `${name}` references
+    /// split out as [`TokenKind::Variable`].
+    fn push_split(&mut self, kind: TokenKind, span: Span, text: &str, modifiers: &[TokenModifier]) {
+        let mut last = 0;
+        let piece = |this: &mut Self, kind, range: Span| {
+            if !range.is_empty() {
+                this.push(
+                    kind,
+                    span.start + range.start..span.start + range.end,
+                    modifiers.to_vec(),
+                );
+            }
+        };
+        for (reference, _) in interpolations(text) {
+            piece(self, kind, last..reference.start);
+            last = reference.end;
+            piece(self, TokenKind::Variable, reference);
+        }
+        piece(self, kind, last..text.len());
+    }
+
+    fn directive(&mut self, node: &SyntaxNode, header: bool) {
+        let name = node
+            .children_with_tokens()
+            .filter_map(|e| e.into_token())
+            .find(|t| t.kind() == SyntaxKind::Ident);
+        let schema = name.as_ref().and_then(|n| schema_for(n.text()));
+        let mut modifiers = Vec::new();
+        if header {
+            modifiers.push(TokenModifier::Declaration);
+        }
+        if schema.is_none() {
+            modifiers.push(TokenModifier::Invalid);
+        }
+        let head_end = name.as_ref().map_or(0, |n| token_span(n).end);
+        let mut args = 0;
+        for child in node.children_with_tokens() {
+            match child {
+                rowan::NodeOrToken::Token(token) => match token.kind() {
+                    SyntaxKind::At | SyntaxKind::Ident if token_span(&token).end <= head_end => {
+                        self.push(TokenKind::Directive, token_span(&token), modifiers.clone())
+                    }
+                    SyntaxKind::Whitespace | SyntaxKind::Newline => {}
+                    SyntaxKind::Comment => {
+                        self.push(TokenKind::Comment, token_span(&token), Vec::new())
+                    }
+                    _ => self.invalid(&token),
+                },
+                rowan::NodeOrToken::Node(child) => match child.kind() {
+                    SyntaxKind::Pair => self.pair(&child, schema),
+                    SyntaxKind::Value | SyntaxKind::List | SyntaxKind::Map => {
+                        // `Some(None)` when the schema has no parameter for it.
+                        let param = schema.map(|s| s.positional.get(args).and_then(|n| s.param(n)));
+                        args += 1;
+                        let text = child.text().to_string();
+                        let modifiers =
+                            if matches!(param, Some(None)) || rejected(param.flatten(), &text) {
+                                vec![TokenModifier::Invalid]
+                            } else {
+                                Vec::new()
+                            };
+                        self.value(&child, &modifiers);
+                    }
+                    _ => tokens(&child).for_each(|t| self.invalid(&t)),
+                },
+            }
+        }
+    }
+
+    fn pair(&mut self, node: &SyntaxNode, schema: Option<&DirectiveSchema>) {
+        // `None` without a schema or for an open one, `Some(None)` for a key
+        // the schema lacks.
+        let param = node
+            .first_token()
+            .filter(|t| t.kind() == SyntaxKind::Ident)
+            .and_then(|key| schema.filter(|s| !s.open).map(|s| s.param(key.text())));
+        for child in node.children_with_tokens() {
+            match child {
+                rowan::NodeOrToken::Token(token) => match token.kind() {
+                    SyntaxKind::Ident => {
+                        let modifiers = match &param {
+                            Some(None) => vec![TokenModifier::Invalid],
+                            _ => Vec::new(),
+                        };
+                        self.push(TokenKind::Key, token_span(&token), modifiers);
+                    }
+                    SyntaxKind::Equals => {
+                        self.push(TokenKind::Operator, token_span(&token), Vec::new())
+                    }
+                    _ => {}
+                },
+                rowan::NodeOrToken::Node(value) => {
+                    let text = value.text().to_string();
+                    let modifiers = if rejected(param.flatten(), &text) {
+                        vec![TokenModifier::Invalid]
+                    } else {
+                        Vec::new()
+                    };
+                    self.value(&value, &modifiers);
+                }
+            }
+        }
+    }
+
+    /// Tokens of a value node; a bare value made of several lexer tokens
+    /// (such as `/a,b`) becomes one token.
+    fn value(&mut self, node: &SyntaxNode, modifiers: &[TokenModifier]) {
+        match node.kind() {
+            SyntaxKind::Value => {
+                let text = node.text().to_string();
+                let kind = if node.first_token().map(|t| t.kind()) == Some(SyntaxKind::String) {
+                    TokenKind::String
+                } else {
+                    match Value::from_bare(&text) {
+                        Value::Int(_) | Value::Float(_) => TokenKind::Number,
+                        Value::Bool(_) => TokenKind::Boolean,
+                        _ => TokenKind::Value,
+                    }
+                };
+                self.push_split(kind, node_span(node), &text, modifiers);
+            }
+            SyntaxKind::List | SyntaxKind::Map | SyntaxKind::Entry => {
+                for child in node.children_with_tokens() {
+                    match child {
+                        rowan::NodeOrToken::Token(token) => match token.kind() {
+                            SyntaxKind::Ident => {
+                                self.push(TokenKind::Key, token_span(&token), modifiers.to_vec())
+                            }
+                            SyntaxKind::Equals => {
+                                self.push(TokenKind::Operator, token_span(&token), Vec::new())
+                            }
+                            SyntaxKind::Whitespace => {}
+                            _ => self.push(
+                                TokenKind::Punctuation,
+                                token_span(&token),
+                                modifiers.to_vec(),
+                            ),
+                        },
+                        rowan::NodeOrToken::Node(child) => self.value(&child, modifiers),
+                    }
+                }
+            }
+            _ => tokens(node).for_each(|t| self.invalid(&t)),
+        }
+    }
+
+    /// A token the grammar could not place.
+    fn invalid(&mut self, token: &SyntaxToken) {
+        let kind = match token.kind() {
+            SyntaxKind::At | SyntaxKind::Ident => TokenKind::Directive,
+            SyntaxKind::Equals => TokenKind::Operator,
+            SyntaxKind::String => TokenKind::String,
+            SyntaxKind::Number => TokenKind::Number,
+            SyntaxKind::Bool => TokenKind::Boolean,
+            SyntaxKind::LBracket
+            | SyntaxKind::RBracket
+            | SyntaxKind::LBrace
+            | SyntaxKind::RBrace
+            | SyntaxKind::Comma => TokenKind::Punctuation,
+            _ => TokenKind::Value,
+        };
+        self.push(kind, token_span(token), vec![TokenModifier::Invalid]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(src: &str) -> Vec<(&str, TokenKind, u32)> {
+        semantic_tokens(src)
+            .into_iter()
+            .map(|t| (&src[t.span.clone()], t.kind, t.modifier_bits()))
+            .collect()
+    }
+
+    #[test]
+    fn classifies_whole_document() {
+        use TokenKind::*;
+        const DECL: u32 = 1 << TokenModifier::Declaration as u32;
+        const INVALID: u32 = 1 << TokenModifier::Invalid as u32;
+        let src = "\n@option points=2 tags=[a, {k=1}] shuffle=maybe\nWhat is 2+2?\n@nope x=\"s\"";
+        assert_eq!(
+            summary(src),
+            [
+                ("@", Directive, DECL),
+                ("option", Directive, DECL),
+                ("points", Key, 0),
+                ("=", Operator, 0),
+                ("2", Number, 0),
+                ("tags", Key, INVALID),
+                ("=", Operator, 0),
+                ("[", Punctuation, 0),
+                ("a", Value, 0),
+                (",", Punctuation, 0),
+                ("{", Punctuation, 0),
+                ("k", Key, 0),
+                ("=", Operator, 0),
+                ("1", Number, 0),
+                ("}", Punctuation, 0),
+                ("]", Punctuation, 0),
+                ("shuffle", Key, 0),
+                ("=", Operator, 0),
+                ("maybe", Value, INVALID),
+                ("What is 2+2?", Text, 0),
+                ("@", Directive, INVALID),
+                ("nope", Directive, INVALID),
+                ("x", Key, 0),
+                ("=", Operator, 0),
+                ("\"s\"", String, 0),
+            ]
+        );
+        let comments = summary("// draft\n@option points=1 # todo\n");
+        assert_eq!(comments[0], ("// draft", Comment, 0));
+        assert_eq!(comments[1], ("@", Directive, DECL));
+        assert_eq!(comments[6], ("# todo", Comment, 0));
+        let variables = summary("@define n=1\n@option prompt=\"Q${n}?\" points=${n}\nWhy ${n}");
+        assert_eq!(
+            variables[2..],
+            [
+                ("n", Key, 0),
+                ("=", Operator, 0),
+                ("1", Number, 0),
+                ("@", Directive, DECL),
+                ("option", Directive, DECL),
+                ("prompt", Key, 0),
+                ("=", Operator, 0),
+                ("\"Q", String, 0),
+                ("${n}", Variable, 0),
+                ("?\"", String, 0),
+                ("points", Key, 0),
+                ("=", Operator, 0),
+                ("${n}", Variable, 0),
+                ("Why ", Text, 0),
+                ("${n}", Variable, 0),
+            ]
+        );
+        let errors = summary("@option a=\"open ]x");
+        assert_eq!(errors[4], ("\"open ]x", String, INVALID));
+    }
+
+    #[test]
+    fn delta_encoding_counts_utf16() {
+        let src = "@option prompt=\"日本😀\" points=1\n\n  body";
+        let tokens = semantic_tokens(src);
+        let data = encode_delta(&tokens);
+        assert_eq!(data.len(), tokens.len() * 5);
+        // `points` follows a 6-unit string (the emoji is a surrogate pair).
+        let n = tokens
+            .iter()
+            .position(|t| &src[t.span.clone()] == "points")
+            .unwrap();
+        assert_eq!(&data[n * 5..n * 5 + 5], [0, 7, 6, TokenKind::Key as u32, 0]);
+        assert_eq!(
+            &data[data.len() - 5..],
+            [2, 2, 4, TokenKind::Text as u32, 0]
+        );
+
+        let json = semantic_tokens_json("@x t=\"a\\\\b\tc\"");
+        let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed[4]["kind"], "string");
+        assert_eq!(parsed[0]["modifiers"][0], "declaration");
+        assert_eq!(
+            serde_json::to_value(LEGEND).unwrap()["tokenModifiers"],
+            serde_json::json!(["declaration", "invalid"])
+        );
+    }
+}
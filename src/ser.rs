@@ -0,0 +1,910 @@
+//! Serialize Rust values as DSL directives with serde.
+//!
+//! The inverse of [`crate::de`]: a struct becomes `@name key=value ...`, named
+//! after the struct (use `#[serde(rename = "...")]` to pick the directive
+//! name), and an enum variant becomes a directive named after the variant. A
+//! scalar variant payload such as `Import::Option(prompt)` is written under
+//! the directive's first schema parameter. Values use [`Value`]'s canonical
+//! spelling, so the output parses back to the same data.
+
+use std::fmt;
+
+use serde::Serialize;
+use serde::ser::{self, Impossible, SerializeMap, SerializeSeq, SerializeStruct};
+
+use crate::schema::schema_for;
+use crate::value::Value;
+
+/// Why a value cannot be written as DSL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error(String);
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn error<T>(msg: impl fmt::Display) -> Result<T, Error> {
+    Err(ser::Error::custom(msg))
+}
+
+/// Write `value` as DSL text, one directive per line. Sequences write one
+/// directive per element; anything else writes a single directive.
+pub fn to_string<T: Serialize + ?Sized>(value: &T) -> Result<String, Error> {
+    let lines = value.serialize(Document)?;
+    let mut out = String::new();
+    for line in lines {
+        out.push_str(&line.to_string());
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// A directive ready to be written.
+struct Line {
+    name: String,
+    pairs: Vec<(String, Value)>,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "@{}", self.name)?;
+        for (key, value) in &self.pairs {
+            write!(f, " {key}={value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Names and keys must read back as identifiers.
+fn check_ident(name: &str) -> Result<(), Error> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        error(format!("`{name}` is not a valid directive name or key"))
+    }
+}
+
+/// Top level: a sequence of directives or a single one.
+struct Document;
+
+macro_rules! forward_to_directive {
+    ($($method:ident($($arg:ident: $ty:ty),*))*) => {
+        $(
+            fn $method(self, $($arg: $ty),*) -> Result<Vec<Line>, Error> {
+                Directive { name: None }.$method($($arg),*).map(|line| vec![line])
+            }
+        )*
+    };
+}
+
+impl ser::Serializer for Document {
+    type Ok = Vec<Line>;
+    type Error = Error;
+    type SerializeSeq = Lines;
+    type SerializeTuple = Lines;
+    type SerializeTupleStruct = Lines;
+    type SerializeTupleVariant = Impossible<Vec<Line>, Error>;
+    type SerializeMap = Single<PairsSer>;
+    type SerializeStruct = Single<PairsSer>;
+    type SerializeStructVariant = Single<PairsSer>;
+
+    forward_to_directive! {
+        serialize_bool(v: bool) serialize_i8(v: i8) serialize_i16(v: i16) serialize_i32(v: i32)
+        serialize_i64(v: i64) serialize_u8(v: u8) serialize_u16(v: u16) serialize_u32(v: u32)
+        serialize_u64(v: u64) serialize_f32(v: f32) serialize_f64(v: f64) serialize_char(v: char)
+        serialize_str(v: &str) serialize_bytes(v: &[u8]) serialize_none()
+        serialize_unit() serialize_unit_struct(name: &'static str)
+        serialize_unit_variant(name: &'static str, index: u32, variant: &'static str)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Vec<Line>, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Vec<Line>, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Vec<Line>, Error> {
+        Directive { name: None }
+            .serialize_newtype_variant(name, index, variant, value)
+            .map(|line| vec![line])
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Lines, Error> {
+        Ok(Lines(Vec::with_capacity(len.unwrap_or(0))))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Lines, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Lines, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        error(format!(
+            "tuple variant `{variant}` cannot be written as a directive"
+        ))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Single<PairsSer>, Error> {
+        Directive { name: None }.serialize_map(len).map(Single)
+    }
+
+    fn serialize_struct(self, name: &'static str, len: usize) -> Result<Single<PairsSer>, Error> {
+        Directive { name: None }
+            .serialize_struct(name, len)
+            .map(Single)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Single<PairsSer>, Error> {
+        Directive { name: None }
+            .serialize_struct_variant(name, index, variant, len)
+            .map(Single)
+    }
+}
+
+/// The elements of a top-level sequence, one directive each.
+struct Lines(Vec<Line>);
+
+impl SerializeSeq for Lines {
+    type Ok = Vec<Line>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.0.push(value.serialize(Directive { name: None })?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Vec<Line>, Error> {
+        Ok(self.0)
+    }
+}
+
+impl ser::SerializeTuple for Lines {
+    type Ok = Vec<Line>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Vec<Line>, Error> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for Lines {
+    type Ok = Vec<Line>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Vec<Line>, Error> {
+        SerializeSeq::end(self)
+    }
+}
+
+/// Wraps a single directive's pair collector for the top level.
+struct Single<S>(S);
+
+impl SerializeMap for Single<PairsSer> {
+    type Ok = Vec<Line>;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.0.serialize_key(key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.0.serialize_value(value)
+    }
+
+    fn end(self) -> Result<Vec<Line>, Error> {
+        SerializeMap::end(self.0).map(|line| vec![line])
+    }
+}
+
+impl SerializeStruct for Single<PairsSer> {
+    type Ok = Vec<Line>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        SerializeStruct::serialize_field(&mut self.0, key, value)
+    }
+
+    fn end(self) -> Result<Vec<Line>, Error> {
+        SerializeStruct::end(self.0).map(|line| vec![line])
+    }
+}
+
+impl ser::SerializeStructVariant for Single<PairsSer> {
+    type Ok = Vec<Line>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        SerializeStruct::serialize_field(&mut self.0, key, value)
+    }
+
+    fn end(self) -> Result<Vec<Line>, Error> {
+        SerializeStruct::end(self.0).map(|line| vec![line])
+    }
+}
+
+/// One directive. `name` is set when an enum variant already chose it.
+struct Directive {
+    name: Option<&'static str>,
+}
+
+impl Directive {
+    /// A scalar payload, written under the directive's first schema parameter.
+    fn scalar(self, value: Option<Value>) -> Result<Line, Error> {
+        let Some(name) = self.name else {
+            return error("a value needs a struct or enum variant to name its directive");
+        };
+        let key = schema_for(name)
+            .and_then(|s| s.params.first())
+            .map_or("value", |p| p.name);
+        Ok(Line {
+            name: name.to_string(),
+            pairs: value.map(|v| (key.to_string(), v)).into_iter().collect(),
+        })
+    }
+
+    fn pairs(name: &str) -> Result<PairsSer, Error> {
+        check_ident(name)?;
+        Ok(PairsSer {
+            name: name.to_string(),
+            pairs: Vec::new(),
+            key: None,
+        })
+    }
+}
+
+macro_rules! scalar_to_value {
+    ($($method:ident($($arg:ident: $ty:ty),*))*) => {
+        $(
+            fn $method(self, $($arg: $ty),*) -> Result<Line, Error> {
+                let value = ValueSer.$method($($arg),*)?;
+                self.scalar(value)
+            }
+        )*
+    };
+}
+
+impl ser::Serializer for Directive {
+    type Ok = Line;
+    type Error = Error;
+    type SerializeSeq = Impossible<Line, Error>;
+    type SerializeTuple = Impossible<Line, Error>;
+    type SerializeTupleStruct = Impossible<Line, Error>;
+    type SerializeTupleVariant = Impossible<Line, Error>;
+    type SerializeMap = PairsSer;
+    type SerializeStruct = PairsSer;
+    type SerializeStructVariant = PairsSer;
+
+    scalar_to_value! {
+        serialize_bool(v: bool) serialize_i8(v: i8) serialize_i16(v: i16) serialize_i32(v: i32)
+        serialize_i64(v: i64) serialize_u8(v: u8) serialize_u16(v: u16) serialize_u32(v: u32)
+        serialize_u64(v: u64) serialize_f32(v: f32) serialize_f64(v: f64) serialize_char(v: char)
+        serialize_str(v: &str) serialize_bytes(v: &[u8]) serialize_none()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Line, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Line, Error> {
+        self.scalar(None)
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<Line, Error> {
+        SerializeStruct::end(Directive::pairs(self.name.unwrap_or(name))?)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Line, Error> {
+        SerializeStruct::end(Directive::pairs(variant)?)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Line, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Line, Error> {
+        check_ident(variant)?;
+        value.serialize(Directive {
+            name: Some(variant),
+        })
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        error("a list cannot be written as a directive")
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
+        error("a tuple cannot be written as a directive")
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Error> {
+        error(format!(
+            "tuple struct `{name}` cannot be written as a directive"
+        ))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        error(format!(
+            "tuple variant `{variant}` cannot be written as a directive"
+        ))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<PairsSer, Error> {
+        match self.name {
+            Some(name) => Directive::pairs(name),
+            None => error("a map needs an enum variant to name its directive"),
+        }
+    }
+
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<PairsSer, Error> {
+        Directive::pairs(self.name.unwrap_or(name))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<PairsSer, Error> {
+        Directive::pairs(variant)
+    }
+}
+
+/// Collects the `key=value` pairs of a directive; `None` fields are left out.
+struct PairsSer {
+    name: String,
+    pairs: Vec<(String, Value)>,
+    key: Option<String>,
+}
+
+impl PairsSer {
+    fn push(&mut self, key: String, value: Option<Value>) -> Result<(), Error> {
+        check_ident(&key)?;
+        self.pairs.extend(value.map(|v| (key, v)));
+        Ok(())
+    }
+}
+
+impl SerializeMap for PairsSer {
+    type Ok = Line;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.key = Some(map_key(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self.key.take().expect("value serialized before its key");
+        self.push(key, value.serialize(ValueSer)?)
+    }
+
+    fn end(self) -> Result<Line, Error> {
+        Ok(Line {
+            name: self.name,
+            pairs: self.pairs,
+        })
+    }
+}
+
+impl SerializeStruct for PairsSer {
+    type Ok = Line;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.push(key.to_string(), value.serialize(ValueSer)?)
+    }
+
+    fn end(self) -> Result<Line, Error> {
+        SerializeMap::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for PairsSer {
+    type Ok = Line;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<Line, Error> {
+        SerializeMap::end(self)
+    }
+}
+
+/// Map keys must be strings (or scalars, written in their DSL spelling).
+fn map_key<T: Serialize + ?Sized>(key: &T) -> Result<String, Error> {
+    match key.serialize(ValueSer)? {
+        Some(Value::String(s)) => Ok(s),
+        Some(v @ (Value::Int(_) | Value::Bool(_))) => Ok(v.to_string()),
+        _ => error("map keys must be strings"),
+    }
+}
+
+/// Serializes a field value into a [`Value`]; `None` means "leave the field out".
+struct ValueSer;
+
+impl ser::Serializer for ValueSer {
+    type Ok = Option<Value>;
+    type Error = Error;
+    type SerializeSeq = ListSer;
+    type SerializeTuple = ListSer;
+    type SerializeTupleStruct = ListSer;
+    type SerializeTupleVariant = Impossible<Option<Value>, Error>;
+    type SerializeMap = MapSer;
+    type SerializeStruct = MapSer;
+    type SerializeStructVariant = Impossible<Option<Value>, Error>;
+
+    fn serialize_bool(self, v: bool) -> Result<Option<Value>, Error> {
+        Ok(Some(Value::Bool(v)))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Option<Value>, Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Option<Value>, Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Option<Value>, Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Option<Value>, Error> {
+        Ok(Some(Value::Int(v)))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Option<Value>, Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Option<Value>, Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Option<Value>, Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Option<Value>, Error> {
+        match i64::try_from(v) {
+            Ok(v) => self.serialize_i64(v),
+            Err(_) => error(format!("{v} does not fit in an integer value")),
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Option<Value>, Error> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Option<Value>, Error> {
+        if v.is_finite() {
+            Ok(Some(Value::Float(v)))
+        } else {
+            error(format!("{v} cannot be written as a number"))
+        }
+    }
+
+    fn serialize_char(self, v: char) -> Result<Option<Value>, Error> {
+        Ok(Some(Value::String(v.to_string())))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Option<Value>, Error> {
+        Ok(Some(Value::literal(v)))
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Option<Value>, Error> {
+        error("bytes cannot be written as a value")
+    }
+
+    fn serialize_none(self) -> Result<Option<Value>, Error> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Option<Value>, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Option<Value>, Error> {
+        Ok(None)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Option<Value>, Error> {
+        Ok(None)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Option<Value>, Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Option<Value>, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Option<Value>, Error> {
+        let mut map = MapSer(Vec::new(), None);
+        map.push(variant.to_string(), value.serialize(ValueSer)?)?;
+        SerializeMap::end(map)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<ListSer, Error> {
+        Ok(ListSer(Vec::with_capacity(len.unwrap_or(0))))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<ListSer, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<ListSer, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        error(format!(
+            "tuple variant `{variant}` cannot be written as a value"
+        ))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<MapSer, Error> {
+        Ok(MapSer(Vec::new(), None))
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<MapSer, Error> {
+        Ok(MapSer(Vec::new(), None))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        error(format!(
+            "struct variant `{variant}` cannot be written as a value"
+        ))
+    }
+}
+
+/// Items of a `[a, b]` list value.
+struct ListSer(Vec<Value>);
+
+impl SerializeSeq for ListSer {
+    type Ok = Option<Value>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        match value.serialize(ValueSer)? {
+            Some(value) => self.0.push(value),
+            None => return error("a list cannot contain an empty value"),
+        }
+        Ok(())
+    }
+
+    fn end(self) -> Result<Option<Value>, Error> {
+        Ok(Some(Value::List(self.0)))
+    }
+}
+
+impl ser::SerializeTuple for ListSer {
+    type Ok = Option<Value>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Option<Value>, Error> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for ListSer {
+    type Ok = Option<Value>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Option<Value>, Error> {
+        SerializeSeq::end(self)
+    }
+}
+
+/// Entries of a `{k=v}` map value, plus the key awaiting its value.
+struct MapSer(Vec<(String, Value)>, Option<String>);
+
+impl MapSer {
+    fn push(&mut self, key: String, value: Option<Value>) -> Result<(), Error> {
+        check_ident(&key)?;
+        self.0.extend(value.map(|v| (key, v)));
+        Ok(())
+    }
+}
+
+impl SerializeMap for MapSer {
+    type Ok = Option<Value>;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.1 = Some(map_key(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self.1.take().expect("value serialized before its key");
+        self.push(key, value.serialize(ValueSer)?)
+    }
+
+    fn end(self) -> Result<Option<Value>, Error> {
+        Ok(Some(Value::Map(self.0)))
+    }
+}
+
+impl SerializeStruct for MapSer {
+    type Ok = Option<Value>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.push(key.to_string(), value.serialize(ValueSer)?)
+    }
+
+    fn end(self) -> Result<Option<Value>, Error> {
+        SerializeMap::end(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::de::from_str;
+    use crate::import::Import;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename = "option")]
+    struct OptionCfg {
+        prompt: String,
+        points: u32,
+        shuffle: bool,
+        ratio: f64,
+        note: Option<String>,
+        tags: Vec<String>,
+        weights: BTreeMap<String, i64>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    enum Header {
+        MultiOption { scoring: String },
+        Meta { author: String },
+        Draft,
+    }
+
+    #[test]
+    fn writes_canonical_dsl() {
+        let cfg = OptionCfg {
+            prompt: "Ibu kota \"Jepang\"?".into(),
+            points: 3,
+            shuffle: true,
+            ratio: 2.0,
+            note: None,
+            tags: vec!["geo".into(), "true".into(), "#1".into(), "a b".into()],
+            weights: BTreeMap::from([("a".into(), 1)]),
+        };
+        let text = to_string(&cfg).unwrap();
+        assert_eq!(
+            text,
+            "@option prompt=\"Ibu kota \\\"Jepang\\\"?\" points=3 shuffle=true ratio=2.0 \
+             tags=[geo, \"true\", \"#1\", \"a b\"] weights={a=1}\n"
+        );
+        assert_eq!(from_str::<OptionCfg>(&text).unwrap(), cfg);
+
+        let import = Import::Option("Pilih satu".into());
+        assert_eq!(
+            to_string(&import).unwrap(),
+            "@option prompt=\"Pilih satu\"\n"
+        );
+        let headers = vec![
+            Header::MultiOption {
+                scoring: "partial".into(),
+            },
+            Header::Meta {
+                author: "日本".into(),
+            },
+            Header::Draft,
+        ];
+        let text = to_string(&headers).unwrap();
+        assert_eq!(
+            text,
+            "@multi_option scoring=partial\n@meta author=日本\n@draft\n"
+        );
+        assert_eq!(from_str::<Vec<Header>>(&text).unwrap(), headers);
+
+        // `${` is escaped rather than read back as a variable reference.
+        let meta = Header::Meta {
+            author: "${x} costs $5, not $${y}".into(),
+        };
+        let text = to_string(&meta).unwrap();
+        assert_eq!(text, "@meta author=\"$${x} costs $5, not $$${y}\"\n");
+        assert_eq!(from_str::<Header>(&text).unwrap(), meta);
+    }
+
+    #[test]
+    fn round_trips_imports_and_nested_enums() {
+        for import in [
+            Import::Option("Pilih satu".into()),
+            Import::MultiOption("Prima?".into()),
+            Import::MatchingPair("Pasangkan".into()),
+        ] {
+            let text = to_string(&import).unwrap();
+            let back: Import = from_str(&text).unwrap();
+            assert_eq!(to_string(&back).unwrap(), text);
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        #[serde(rename_all = "snake_case")]
+        enum Level {
+            Easy,
+            Hard,
+        }
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        #[serde(rename = "meta")]
+        struct Meta {
+            difficulty: Level,
+            levels: Vec<Level>,
+            fallback: Option<Level>,
+        }
+        let meta = Meta {
+            difficulty: Level::Hard,
+            levels: vec![Level::Easy, Level::Hard],
+            fallback: Some(Level::Easy),
+        };
+        let text = to_string(&meta).unwrap();
+        assert_eq!(
+            text,
+            "@meta difficulty=hard levels=[easy, hard] fallback=easy\n"
+        );
+        assert_eq!(from_str::<Meta>(&text).unwrap(), meta);
+    }
+
+    #[test]
+    fn rejects_unwritable_values() {
+        assert!(to_string(&5).is_err());
+        assert!(to_string(&BTreeMap::from([("a", 1)])).is_err());
+        #[derive(Serialize)]
+        struct Bad {
+            #[serde(rename = "not a key")]
+            x: u8,
+        }
+        assert_eq!(
+            to_string(&Bad { x: 1 }).unwrap_err().to_string(),
+            "`not a key` is not a valid directive name or key"
+        );
+        #[derive(Serialize)]
+        struct Nan {
+            x: f64,
+        }
+        assert!(to_string(&Nan { x: f64::NAN }).is_err());
+    }
+}
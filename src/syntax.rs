@@ -0,0 +1,627 @@
+//! Lossless concrete syntax tree.
+//!
+//! Every byte of the source, including whitespace and line endings, ends up in
+//! exactly one token, so `parse_syntax(src).to_string() == src` always holds.
+//! The typed views in [`ast`] sit on top of the untyped rowan tree.
+
+use rowan::{GreenNode, GreenNodeBuilder, GreenToken, NodeOrToken};
+
+use crate::parser::{is_comment_line, lex_line};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u16)]
+pub enum SyntaxKind {
+    // Tokens.
+    Whitespace,
+    Newline,
+    At,
+    Ident,
+    Equals,
+    String,
+    Word,
+    Number,
+    Bool,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Comma,
+    Text,
+    Comment,
+    // Nodes.
+    Root,
+    Directive,
+    Pair,
+    Value,
+    List,
+    Map,
+    Entry,
+    TextLine,
+    BlankLine,
+    CommentLine,
+    Error,
+}
+
+use SyntaxKind::*;
+
+const KINDS: [SyntaxKind; 27] = [
+    Whitespace,
+    Newline,
+    At,
+    Ident,
+    Equals,
+    String,
+    Word,
+    Number,
+    Bool,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Comma,
+    Text,
+    Comment,
+    Root,
+    Directive,
+    Pair,
+    Value,
+    List,
+    Map,
+    Entry,
+    TextLine,
+    BlankLine,
+    CommentLine,
+    Error,
+];
+
+impl From<SyntaxKind> for rowan::SyntaxKind {
+    fn from(kind: SyntaxKind) -> Self {
+        rowan::SyntaxKind(kind as u16)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Lang {}
+
+impl rowan::Language for Lang {
+    type Kind = SyntaxKind;
+
+    fn kind_from_raw(raw: rowan::SyntaxKind) -> SyntaxKind {
+        KINDS[raw.0 as usize]
+    }
+
+    fn kind_to_raw(kind: SyntaxKind) -> rowan::SyntaxKind {
+        kind.into()
+    }
+}
+
+pub type SyntaxNode = rowan::SyntaxNode<Lang>;
+pub type SyntaxToken = rowan::SyntaxToken<Lang>;
+pub type SyntaxElement = rowan::SyntaxElement<Lang>;
+
+/// Build the lossless tree for a whole document.
+pub fn parse_syntax(src: &str) -> SyntaxNode {
+    let mut builder = GreenNodeBuilder::new();
+    builder.start_node(Root.into());
+    for raw in src.split_inclusive('\n') {
+        build_line(&mut builder, raw);
+    }
+    builder.finish_node();
+    SyntaxNode::new_root(builder.finish())
+}
+
+/// Split a raw line into its content and its line terminator.
+fn split_newline(raw: &str) -> (&str, &str) {
+    let content = raw.strip_suffix('\n').unwrap_or(raw);
+    let content = content.strip_suffix('\r').unwrap_or(content);
+    raw.split_at(content.len())
+}
+
+fn build_line(builder: &mut GreenNodeBuilder<'static>, raw: &str) {
+    let (line, newline) = split_newline(raw);
+    let content = line.trim_start();
+    let indent = &line[..line.len() - content.len()];
+    let kind = if content.is_empty() {
+        BlankLine
+    } else if is_comment_line(content) {
+        CommentLine
+    } else if content.starts_with('@') {
+        Directive
+    } else {
+        TextLine
+    };
+    builder.start_node(kind.into());
+    if !indent.is_empty() {
+        builder.token(Whitespace.into(), indent);
+    }
+    match kind {
+        Directive => {
+            let tokens = lex_tokens(content);
+            LineParser::new(builder, &tokens).directive();
+        }
+        TextLine | CommentLine => {
+            let text = content.trim_end();
+            let token = if kind == TextLine { Text } else { Comment };
+            builder.token(token.into(), text);
+            if text.len() < content.len() {
+                builder.token(Whitespace.into(), &content[text.len()..]);
+            }
+        }
+        _ => {}
+    }
+    if !newline.is_empty() {
+        builder.token(Newline.into(), newline);
+    }
+    builder.finish_node();
+}
+
+fn lex_tokens(text: &str) -> Vec<(SyntaxKind, std::string::String)> {
+    lex_line(text)
+        .into_iter()
+        .map(|t| {
+            let kind = match t.kind {
+                "At" => At,
+                "Ident" => Ident,
+                "Equals" => Equals,
+                "Ws" => Whitespace,
+                "String" => String,
+                "Number" => Number,
+                "Bool" => Bool,
+                "LBracket" => LBracket,
+                "RBracket" => RBracket,
+                "LBrace" => LBrace,
+                "RBrace" => RBrace,
+                "Comma" => Comma,
+                "Comment" => Comment,
+                _ => Word,
+            };
+            (kind, t.text)
+        })
+        .collect()
+}
+
+/// Groups the tokens of one directive line into nodes. Mirrors the grammar of
+/// `parser::directive_parser`; anything it cannot place goes into an `Error` node.
+struct LineParser<'b, 't> {
+    builder: &'b mut GreenNodeBuilder<'static>,
+    tokens: &'t [(SyntaxKind, std::string::String)],
+    pos: usize,
+}
+
+impl<'b, 't> LineParser<'b, 't> {
+    fn new(
+        builder: &'b mut GreenNodeBuilder<'static>,
+        tokens: &'t [(SyntaxKind, std::string::String)],
+    ) -> Self {
+        LineParser {
+            builder,
+            tokens,
+            pos: 0,
+        }
+    }
+
+    fn nth(&self, n: usize) -> Option<SyntaxKind> {
+        self.tokens.get(self.pos + n).map(|(k, _)| *k)
+    }
+
+    fn peek(&self) -> Option<SyntaxKind> {
+        self.nth(0)
+    }
+
+    fn bump(&mut self) {
+        let (kind, text) = &self.tokens[self.pos];
+        self.builder.token((*kind).into(), text);
+        self.pos += 1;
+    }
+
+    fn eat(&mut self, kind: SyntaxKind) -> bool {
+        let found = self.peek() == Some(kind);
+        if found {
+            self.bump();
+        }
+        found
+    }
+
+    fn bump_while(&mut self, keep: impl Fn(SyntaxKind) -> bool) {
+        while self.peek().is_some_and(&keep) {
+            self.bump();
+        }
+    }
+
+    /// Whether the next tokens are `ident ws? =`.
+    fn at_pair(&self) -> bool {
+        if self.peek() != Some(Ident) {
+            return false;
+        }
+        match self.nth(1) {
+            Some(Whitespace) => self.nth(2) == Some(Equals),
+            next => next == Some(Equals),
+        }
+    }
+
+    fn error_until(&mut self, stop: impl Fn(SyntaxKind) -> bool) {
+        self.builder.start_node(Error.into());
+        self.bump();
+        self.bump_while(|k| !stop(k));
+        self.builder.finish_node();
+    }
+
+    /// The tokens of a directive line, without indentation or line terminator.
+    fn directive(mut self) {
+        self.eat(At);
+        if self.peek().is_some_and(|k| k != Whitespace) && !self.eat(Ident) {
+            self.error_until(|k| k == Whitespace);
+        }
+        loop {
+            self.eat(Whitespace);
+            match self.peek() {
+                None => break,
+                Some(Comment) => self.bump(),
+                Some(_) if self.at_pair() => self.pair(),
+                Some(_) => self.positional(),
+            }
+        }
+    }
+
+    /// A positional argument: a value directly under the directive. A bare
+    /// one holding `=` matches neither a pair nor an argument.
+    fn positional(&mut self) {
+        let bare = !matches!(self.peek(), Some(String | LBracket | LBrace));
+        let mut word = self.tokens[self.pos..]
+            .iter()
+            .take_while(|(k, _)| *k != Whitespace);
+        if bare && word.any(|(k, _)| *k == Equals) {
+            self.error_until(|k| k == Whitespace);
+        } else {
+            self.value(true);
+        }
+    }
+
+    fn pair(&mut self) {
+        self.builder.start_node(Pair.into());
+        self.bump();
+        self.eat(Whitespace);
+        self.eat(Equals);
+        self.eat(Whitespace);
+        if self.peek().is_some_and(|k| k != Comment) {
+            self.value(true);
+        }
+        self.builder.finish_node();
+    }
+
+    /// A value; bare words at the top level only stop at whitespace, nested
+    /// ones also at `,`, `]` and `}`.
+    fn value(&mut self, top: bool) {
+        match self.peek() {
+            Some(LBracket) => self.list(),
+            Some(LBrace) => self.map(),
+            Some(String) => {
+                self.builder.start_node(Value.into());
+                self.bump();
+                self.builder.finish_node();
+            }
+            _ => {
+                self.builder.start_node(Value.into());
+                self.bump();
+                self.bump_while(|k| {
+                    k != Whitespace && (top || !matches!(k, Comma | RBracket | RBrace))
+                });
+                self.builder.finish_node();
+            }
+        }
+    }
+
+    fn list(&mut self) {
+        self.builder.start_node(List.into());
+        self.bump();
+        loop {
+            self.eat(Whitespace);
+            match self.peek() {
+                None | Some(Comment) => break,
+                Some(RBracket) => {
+                    self.bump();
+                    break;
+                }
+                Some(Comma) => self.bump(),
+                Some(RBrace) => self.error_until(|k| matches!(k, Whitespace | Comma | RBracket)),
+                Some(_) => self.value(false),
+            }
+        }
+        self.builder.finish_node();
+    }
+
+    fn map(&mut self) {
+        self.builder.start_node(Map.into());
+        self.bump();
+        loop {
+            self.eat(Whitespace);
+            match self.peek() {
+                None | Some(Comment) => break,
+                Some(RBrace) => {
+                    self.bump();
+                    break;
+                }
+                Some(Comma) => self.bump(),
+                Some(Ident) => self.entry(),
+                Some(_) => self.error_until(|k| matches!(k, Whitespace | Comma | RBrace)),
+            }
+        }
+        self.builder.finish_node();
+    }
+
+    fn entry(&mut self) {
+        self.builder.start_node(Entry.into());
+        self.bump();
+        self.eat(Whitespace);
+        if self.eat(Equals) {
+            self.eat(Whitespace);
+            if self
+                .peek()
+                .is_some_and(|k| !matches!(k, Whitespace | Comma | RBrace | Comment))
+            {
+                self.value(false);
+            }
+        }
+        self.builder.finish_node();
+    }
+}
+
+/// Green node for a standalone value, used to splice new values into a tree.
+fn green_value(value: &crate::value::Value) -> GreenNode {
+    let tokens = lex_tokens(&value.to_string());
+    let mut builder = GreenNodeBuilder::new();
+    LineParser::new(&mut builder, &tokens).value(true);
+    builder.finish()
+}
+
+/// Typed views over [`SyntaxNode`]s.
+pub mod ast {
+    use super::*;
+
+    pub trait AstNode: Sized {
+        fn cast(node: SyntaxNode) -> Option<Self>;
+        fn syntax(&self) -> &SyntaxNode;
+    }
+
+    macro_rules! ast_node {
+        ($name:ident, $($kind:ident)|+) => {
+            #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+            pub struct $name(SyntaxNode);
+
+            impl AstNode for $name {
+                fn cast(node: SyntaxNode) -> Option<Self> {
+                    matches!(node.kind(), $(SyntaxKind::$kind)|+).then(|| $name(node))
+                }
+
+                fn syntax(&self) -> &SyntaxNode {
+                    &self.0
+                }
+            }
+        };
+    }
+
+    ast_node!(Root, Root);
+    ast_node!(Directive, Directive);
+    ast_node!(Pair, Pair);
+    ast_node!(Value, Value | List | Map);
+    ast_node!(List, List);
+    ast_node!(Map, Map);
+    ast_node!(Entry, Entry);
+
+    fn children<N: AstNode>(node: &SyntaxNode) -> impl Iterator<Item = N> + use<N> {
+        node.children().filter_map(N::cast)
+    }
+
+    fn first_token(node: &SyntaxNode, kind: SyntaxKind) -> Option<SyntaxToken> {
+        node.children_with_tokens()
+            .filter_map(NodeOrToken::into_token)
+            .find(|t| t.kind() == kind)
+    }
+
+    /// Replace `node` inside its tree and return the new root.
+    fn replace(node: &SyntaxNode, green: GreenNode) -> Root {
+        Root(SyntaxNode::new_root(node.replace_with(green)))
+    }
+
+    impl Root {
+        pub fn parse(src: &str) -> Root {
+            Root(parse_syntax(src))
+        }
+
+        pub fn directives(&self) -> impl Iterator<Item = Directive> + use<> {
+            children(&self.0)
+        }
+
+        /// The directive on the first non-blank line, if that line is one.
+        pub fn header(&self) -> Option<Directive> {
+            self.headers().next()
+        }
+
+        /// The header block: the run of directive lines starting at the
+        /// first non-blank line. Comment lines are skipped.
+        pub fn headers(&self) -> impl Iterator<Item = Directive> + use<> {
+            self.0
+                .children()
+                .filter(|n| n.kind() != SyntaxKind::CommentLine)
+                .skip_while(|n| n.kind() == SyntaxKind::BlankLine)
+                .map_while(Directive::cast)
+        }
+    }
+
+    impl Directive {
+        pub fn name(&self) -> Option<SyntaxToken> {
+            first_token(&self.0, SyntaxKind::Ident)
+        }
+
+        /// The positional arguments, in source order.
+        pub fn args(&self) -> impl Iterator<Item = Value> + use<> {
+            children(&self.0)
+        }
+
+        pub fn pairs(&self) -> impl Iterator<Item = Pair> + use<> {
+            children(&self.0)
+        }
+
+        pub fn pair(&self, key: &str) -> Option<Pair> {
+            self.pairs()
+                .find(|p| p.key().is_some_and(|k| k.text() == key))
+        }
+    }
+
+    impl Pair {
+        pub fn key(&self) -> Option<SyntaxToken> {
+            first_token(&self.0, SyntaxKind::Ident)
+        }
+
+        pub fn value(&self) -> Option<Value> {
+            children(&self.0).next()
+        }
+
+        /// The tree with this pair's key renamed; all other text is kept.
+        pub fn with_key(&self, key: &str) -> Root {
+            let token = GreenToken::new(SyntaxKind::Ident.into(), key);
+            let green = self.0.green().replace_child(0, token.into());
+            replace(&self.0, green)
+        }
+
+        /// The tree with this pair's value replaced; all other text is kept.
+        pub fn with_value(&self, value: &crate::value::Value) -> Root {
+            let new = green_value(value);
+            let green = match self.value() {
+                Some(old) => self.0.green().replace_child(old.0.index(), new.into()),
+                None => {
+                    let end = self.0.green().children().len();
+                    self.0.green().insert_child(end, new.into())
+                }
+            };
+            replace(&self.0, green)
+        }
+    }
+
+    impl Value {
+        /// The typed value, or `None` if this node does not form a valid literal.
+        pub fn to_value(&self) -> Option<crate::value::Value> {
+            crate::parser::parse_value(&self.0.text().to_string())
+        }
+
+        pub fn as_list(&self) -> Option<List> {
+            List::cast(self.0.clone())
+        }
+
+        pub fn as_map(&self) -> Option<Map> {
+            Map::cast(self.0.clone())
+        }
+    }
+
+    impl List {
+        pub fn items(&self) -> impl Iterator<Item = Value> + use<> {
+            children(&self.0)
+        }
+    }
+
+    impl Map {
+        pub fn entries(&self) -> impl Iterator<Item = Entry> + use<> {
+            children(&self.0)
+        }
+    }
+
+    impl Entry {
+        pub fn key(&self) -> Option<SyntaxToken> {
+            first_token(&self.0, SyntaxKind::Ident)
+        }
+
+        pub fn value(&self) -> Option<Value> {
+            children(&self.0).next()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ast::{AstNode, Root};
+    use super::*;
+    use crate::value::Value as V;
+
+    #[test]
+    fn round_trips_exactly() {
+        for src in [
+            "",
+            "\n\n",
+            "  @option   count = 10\tshuffle=true  \r\nWhat is 2+2?  \n\n@x",
+            "@option a=[1, {k=v,}, \"s\"] b= c=\"open\n@ =1 ]]\r",
+            "@option\u{3000}x=日本語 y='it\\'s'\n  plain text\t\n",
+            "# note \n@option x= # c\n@x a=[1, # c\n  // c\t\r\n",
+        ] {
+            assert_eq!(parse_syntax(src).to_string(), src);
+        }
+        let comments: Vec<_> = parse_syntax("# a\n@option x=1 # b\n")
+            .descendants_with_tokens()
+            .filter_map(|e| e.into_token())
+            .filter(|t| t.kind() == Comment)
+            .map(|t| t.text().to_string())
+            .collect();
+        assert_eq!(comments, ["# a", "# b"]);
+    }
+
+    #[test]
+    fn typed_views() {
+        let root = Root::parse("\n@option  count = 10 tags=[a, [b]] map={k=v}\nbody\n@meta x=1\n");
+        let header = root.header().unwrap();
+        assert_eq!(header.name().unwrap().text(), "option");
+        let keys: Vec<_> = header
+            .pairs()
+            .map(|p| p.key().unwrap().text().to_string())
+            .collect();
+        assert_eq!(keys, ["count", "tags", "map"]);
+        assert_eq!(
+            header.pair("count").unwrap().value().unwrap().to_value(),
+            Some(V::Int(10))
+        );
+        let tags = header.pair("tags").unwrap().value().unwrap();
+        assert_eq!(tags.as_list().unwrap().items().count(), 2);
+        let entry = header
+            .pair("map")
+            .unwrap()
+            .value()
+            .unwrap()
+            .as_map()
+            .unwrap();
+        assert_eq!(entry.entries().next().unwrap().key().unwrap().text(), "k");
+        assert_eq!(root.directives().count(), 2);
+        assert!(Root::parse("text\n@option").header().is_none());
+        let headers = Root::parse("\n@option\n@meta x=1\n\n@note\n")
+            .headers()
+            .count();
+        assert_eq!(headers, 2);
+    }
+
+    #[test]
+    fn edits_keep_surrounding_text() {
+        let root = Root::parse("@option  count = 10   shuffle=true # keep\nbody\n");
+        let pair = root.header().unwrap().pair("count").unwrap();
+        let edited = pair.with_value(&V::String("two words".into()));
+        assert_eq!(
+            edited.syntax().to_string(),
+            "@option  count = \"two words\"   shuffle=true # keep\nbody\n"
+        );
+        let renamed = edited
+            .header()
+            .unwrap()
+            .pair("shuffle")
+            .unwrap()
+            .with_key("random");
+        assert_eq!(
+            renamed.syntax().to_string(),
+            "@option  count = \"two words\"   random=true # keep\nbody\n"
+        );
+        let filled = Root::parse("@option count=")
+            .header()
+            .unwrap()
+            .pair("count")
+            .unwrap();
+        assert_eq!(
+            filled.with_value(&V::Int(3)).syntax().to_string(),
+            "@option count=3"
+        );
+    }
+}
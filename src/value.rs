@@ -0,0 +1,210 @@
+use std::fmt;
+
+use crate::eval::{ESCAPE, interpolations};
+use crate::parser::is_comment_line;
+
+/// A typed directive value.
+///
+/// Bare words are classified by [`Value::from_bare`]; quoted text is always a
+/// [`Value::String`]. Maps keep their entries in source order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+    List(Vec<Value>),
+    Map(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// A string that evaluates to exactly `text`: each `${` is escaped as
+    /// `$${`, so it is not read as a variable reference.
+    pub fn literal(text: &str) -> Value {
+        Value::String(text.replace("${", ESCAPE))
+    }
+
+    /// Classify an unquoted word: `true`/`false`, integers, finite floats,
+    /// otherwise a string.
+    pub fn from_bare(word: &str) -> Value {
+        match word {
+            "true" => return Value::Bool(true),
+            "false" => return Value::Bool(false),
+            _ => {}
+        }
+        if let Ok(i) = word.parse::<i64>() {
+            return Value::Int(i);
+        }
+        // Out of range floats such as `1e400` stay strings: `inf` would not
+        // read back as a float.
+        if looks_numeric(word)
+            && let Ok(f) = word.parse::<f64>()
+            && f.is_finite()
+        {
+            return Value::Float(f);
+        }
+        Value::String(word.to_string())
+    }
+
+    /// Name of the value's type as used in diagnostics.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "boolean",
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Int(i) => Some(*i as f64),
+            Value::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_map(&self) -> Option<&[(String, Value)]> {
+        match self {
+            Value::Map(entries) => Some(entries),
+            _ => None,
+        }
+    }
+}
+
+/// Digits with an optional sign, fraction and exponent; rules out `inf`, `NaN` etc.
+fn looks_numeric(word: &str) -> bool {
+    let digits = word.trim_start_matches(['-', '+']);
+    digits.starts_with(|c: char| c.is_ascii_digit())
+        && digits
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '-' | '+'))
+}
+
+/// Whether `s` can be written without quotes and still read back as the same
+/// string, without looking like a comment.
+fn is_bare_safe(s: &str) -> bool {
+    // The braces of `${name}` references read back fine in bare words.
+    let mut outside = String::new();
+    let mut last = 0;
+    for (span, _) in interpolations(s) {
+        outside.push_str(&s[last..span.start]);
+        last = span.end;
+    }
+    outside.push_str(&s[last..]);
+    !s.is_empty()
+        && !s.starts_with(['"', '\''])
+        && !is_comment_line(s)
+        && !outside
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '[' | ']' | '{' | '}' | ',' | '=' | '\\'))
+        && matches!(Value::from_bare(s), Value::String(_))
+}
+
+fn write_quoted(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\t' => f.write_str("\\t")?,
+            c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    f.write_str("\"")
+}
+
+/// Canonical DSL spelling; parsing the output yields an equal value.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{i}"),
+            Value::Float(x) => write!(f, "{x:?}"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::String(s) if is_bare_safe(s) => f.write_str(s),
+            Value::String(s) => write_quoted(f, s),
+            Value::List(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                f.write_str("]")
+            }
+            Value::Map(entries) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{key}={value}")?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Self {
+        Value::Int(i)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Self {
+        Value::Float(x)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}